@@ -1,7 +1,7 @@
 use std::time::Instant;
 
 use argh::{FromArgs, from_env};
-use inserter_x::clickhouse::ClickhouseInserter;
+use inserter_x::{clickhouse::ClickhouseInserter, common::Inserter};
 use polars::{
     io::SerReader,
     prelude::{CsvParseOptions, CsvReadOptions},
@@ -1,29 +1,711 @@
+#[cfg(feature = "client")]
+pub mod client;
+pub mod describe;
+pub mod exception;
+
 use std::{
     collections::{HashMap, HashSet},
     sync::Arc,
 };
 
+use polars::prelude::{ChunkFull, IntoColumn, IntoSeries, NamedFrom};
+
+use crate::clickhouse::describe::{
+    NullabilityConflict, SchemaReport, TableDescription, TypeMismatch, clickhouse_semantic,
+    clickhouse_temporal_range, clickhouse_to_arrow_datatype, clickhouse_types_match,
+};
 use crate::common::{
-    ArField, CreateCmd, InsError, InsResult, PlArrowDtype, PlColumn, arrow_to_bytes,
-    polars_to_arrow_datatype,
+    ArDtype, ArField, ArFields, ArrowStreamChunks, CreateCmd, DEFAULT_MAX_BATCH_ROWS, InsError,
+    InsResult, Inserter, PlArrowDtype, PlArrowField, PlColumn, PlDtype, SEMANTIC_METADATA,
+    SOURCE_PATH_METADATA, SOURCE_PATH_SEPARATOR, Semantic, TableSpec, arrow_to_bytes,
+    arrow_to_writer, polars_to_arrow_datatype,
 };
 
 pub struct ClickhouseInserter {
-    pub override_creation: Option<String>,
-    pub table_name: String,
-    pub db_name: Option<String>,
-    pub fields: HashMap<String, Option<PlArrowDtype>>,
+    pub spec: TableSpec,
     pub engine: Option<String>,
-    pub not_null: HashSet<String>,
-    pub override_fields: HashMap<String, String>,
-    pub order_by: Vec<String>,
-    pub primary_key: Vec<String>,
+    pub column_options: HashMap<String, ColumnOptions>,
+    pub partition_by: Vec<String>,
+    pub sample_by: Option<String>,
+    pub ttl: Vec<(String, String)>,
+    pub table_settings: Vec<(String, String)>,
+    pub key_expressions: HashSet<String>,
+    pub map_columns: HashSet<String>,
+    pub type_options: TypeOptions,
+    pub table_description: Option<TableDescription>,
+    pub cast_to_table: bool,
+    pub migration_policy: MigrationPolicy,
+    pub null_policy: NullPolicy,
+    pub range_policy: RangePolicy,
+    pub max_batch_rows: usize,
+    declared_types: HashMap<String, String>,
     schema: Arc<arrow::datatypes::Schema>,
     cached_create_query: Option<String>,
     cached_insert_query: Option<String>,
+    cached_alter_queries: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeMapping {
+    /// `Time` / `Time64(p)`, only available on ClickHouse 25.6 and later.
+    Time64,
+    /// `Int32` / `Int64` counting the column's time unit since midnight.
+    SinceMidnight,
+    /// `String` formatted as `HH:MM:SS[.fraction]`.
+    #[default]
+    String,
+}
+
+/// What encoding the body does with nulls in top-level NOT NULL columns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NullPolicy {
+    /// Fail with `InsError::NullError` listing every offending column.
+    #[default]
+    Reject,
+    /// Replace the nulls with the ClickHouse default of the column type:
+    /// zero, the empty string, the epoch or the first enum value.
+    FillDefault,
+    /// Leave out the rows holding a null in any NOT NULL column.
+    DropRows,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NullViolation {
+    pub column: String,
+    pub null_count: usize,
+    /// The first few rows holding a null, counted from the start of the frame.
+    pub first_rows: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DateMapping {
+    /// `Date32`, from 1900 to 2299.
+    #[default]
+    Wide,
+    /// `Date`, from 1970 to 2149. `with_schema_from_cols` declares columns
+    /// holding dates beyond that as `Date32`.
+    Narrow,
+}
+
+/// What encoding the body does with Date and DateTime values outside the
+/// range of their ClickHouse column type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RangePolicy {
+    /// Fail with `InsError::RangeError` listing every offending column.
+    #[default]
+    Reject,
+    /// Replace the values with the nearest end of the range.
+    Clamp,
+    /// Replace the values with nulls, before the [`NullPolicy`] applies.
+    Null,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeViolation {
+    pub column: String,
+    pub ch_type: String,
+    pub count: usize,
+    /// The first few rows out of range, counted from the start of the frame.
+    pub first_rows: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StructMapping {
+    /// `Tuple(name T, ...)`.
+    #[default]
+    NamedTuple,
+    /// Top-level lists of structs become `Nested(name T, ...)`, other
+    /// structs stay named tuples.
+    Nested,
+    /// Struct fields become top-level columns named by joining their path
+    /// with `flatten_separator`, unnesting at most `flatten_depth` levels.
+    Flatten,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NestedNulls {
+    /// Null arrays and maps become empty, null tuples become tuples of
+    /// default (or NULL) members.
+    #[default]
+    Defaults,
+    /// Tuples are declared `Nullable(Tuple(...))`, which needs ClickHouse
+    /// 25.x with nullable tuples enabled. Arrays and maps are still emptied.
+    NullableTuple,
+}
+
+#[derive(Debug, Clone)]
+pub struct TypeOptions {
+    pub time: TimeMapping,
+    pub dates: DateMapping,
+    pub structs: StructMapping,
+    pub nested_nulls: NestedNulls,
+    pub flatten_separator: String,
+    pub flatten_depth: Option<usize>,
+}
+
+impl Default for TypeOptions {
+    fn default() -> Self {
+        Self {
+            time: TimeMapping::default(),
+            dates: DateMapping::default(),
+            structs: StructMapping::default(),
+            nested_nulls: NestedNulls::default(),
+            flatten_separator: "_".to_owned(),
+            flatten_depth: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MigrationPolicy {
+    /// Only add the columns missing from the table.
+    #[default]
+    AdditiveOnly,
+    /// Also modify existing columns whose type or nullability differs.
+    Permissive,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColumnDefault {
+    Default(String),
+    Materialized(String),
+    Alias(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ColumnOptions {
+    pub codec: Vec<String>,
+    pub default: Option<ColumnDefault>,
+    pub comment: Option<String>,
+    pub low_cardinality: bool,
+    pub fixed_string: Option<usize>,
+    pub semantic: Option<Semantic>,
+}
+
+fn sql_string(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn clickhouse_datetime(tu: &polars::prelude::ArrowTimeUnit, tz: Option<&str>) -> String {
+    let precision = match tu {
+        polars::prelude::ArrowTimeUnit::Second => None,
+        polars::prelude::ArrowTimeUnit::Millisecond => Some(3),
+        polars::prelude::ArrowTimeUnit::Microsecond => Some(6),
+        polars::prelude::ArrowTimeUnit::Nanosecond => Some(9),
+    };
+    match (precision, tz) {
+        (None, None) => "DateTime".to_owned(),
+        (None, Some(z)) => format!("DateTime({})", sql_string(z)),
+        (Some(p), None) => format!("DateTime64({})", p),
+        (Some(p), Some(z)) => format!("DateTime64({}, {})", p, sql_string(z)),
+    }
+}
+
+fn clickhouse_time(
+    tu: &polars::prelude::ArrowTimeUnit,
+    is_time64: bool,
+    mapping: TimeMapping,
+) -> String {
+    match mapping {
+        TimeMapping::Time64 => match tu {
+            polars::prelude::ArrowTimeUnit::Second => "Time".to_owned(),
+            polars::prelude::ArrowTimeUnit::Millisecond => "Time64(3)".to_owned(),
+            polars::prelude::ArrowTimeUnit::Microsecond => "Time64(6)".to_owned(),
+            polars::prelude::ArrowTimeUnit::Nanosecond => "Time64(9)".to_owned(),
+        },
+        TimeMapping::SinceMidnight if is_time64 => "Int64".to_owned(),
+        TimeMapping::SinceMidnight => "Int32".to_owned(),
+        TimeMapping::String => "String".to_owned(),
+    }
+}
+
+// polars reports dictionaries as nested, but they hold plain values
+fn is_nested_type(pl: &PlArrowDtype) -> bool {
+    pl.is_nested() && !matches!(pl, PlArrowDtype::Dictionary(..))
+}
+
+/// Whether a column or member of type `pl` can be declared Nullable:
+/// arrays and maps never are, tuples only under [`NestedNulls::NullableTuple`].
+fn is_nullable_member(is_nullable: bool, pl: &PlArrowDtype, opts: &TypeOptions) -> bool {
+    match pl {
+        PlArrowDtype::Struct(_) => is_nullable && opts.nested_nulls == NestedNulls::NullableTuple,
+        x => is_nullable && !is_nested_type(x),
+    }
+}
+
+/// Arrow counterpart of [`is_nullable_member`]; nulls in non-nullable nested
+/// fields are replaced with defaults when the body is encoded.
+fn is_nullable_arrow(is_nullable: bool, adt: &ArDtype, opts: &TypeOptions) -> bool {
+    match adt {
+        ArDtype::Struct(_) => is_nullable && opts.nested_nulls == NestedNulls::NullableTuple,
+        ArDtype::List(_)
+        | ArDtype::LargeList(_)
+        | ArDtype::FixedSizeList(..)
+        | ArDtype::Map(..) => false,
+        _ => is_nullable,
+    }
 }
 
-fn polars_to_clickhouse_sql(pl: &PlArrowDtype) -> InsResult<String> {
+fn clickhouse_enum<'a>(categories: impl ExactSizeIterator<Item = &'a str>) -> InsResult<String> {
+    let enum_type = match categories.len() {
+        0 => {
+            return Err(InsError::BuildError(
+                "clickhouse enum",
+                "an Enum needs at least one category".to_owned(),
+            ));
+        }
+        1..=127 => "Enum8",
+        128..=32767 => "Enum16",
+        n => {
+            return Err(InsError::BuildError(
+                "clickhouse enum",
+                format!("{} categories exceed the 32767 allowed by Enum16", n),
+            ));
+        }
+    };
+    let values = categories
+        .enumerate()
+        .map(|(idx, x)| format!("{} = {}", sql_string(x), idx + 1))
+        .collect::<Vec<_>>();
+    Ok(format!("{}({})", enum_type, values.join(", ")))
+}
+
+const ROWS_REPORTED: usize = 5;
+
+/// Path of a flattened field within its source column, starting with the
+/// column name.
+fn source_path(field: &ArField) -> Option<Vec<&str>> {
+    field
+        .metadata()
+        .get(SOURCE_PATH_METADATA)
+        .map(|x| x.split(SOURCE_PATH_SEPARATOR).collect())
+}
+
+/// Rows of `frame` where the values of `field` are null, flattened fields
+/// counting the nulls of their parent structs too. None for nullable or
+/// nested fields, encoding replaces the nulls of the latter already.
+fn field_nulls(
+    field: &ArField,
+    frame: &polars::prelude::DataFrame,
+) -> Option<polars::prelude::BooleanChunked> {
+    if field.is_nullable() {
+        return None;
+    }
+    let path = source_path(field);
+    let name = path.as_ref().map(|x| x[0]).unwrap_or(field.name());
+    let mut series = frame.column(name).ok()?.as_materialized_series().clone();
+    let mut nulls = series.is_null();
+    for segment in path.iter().flat_map(|x| &x[1..]) {
+        series = series.struct_().ok()?.field_by_name(segment).ok()?;
+        nulls = &nulls | &series.is_null();
+    }
+    if series.dtype().is_nested() {
+        return None;
+    }
+    Some(nulls)
+}
+
+/// NOT NULL fields of `schema` whose values in `frame` hold nulls.
+fn null_violations(
+    schema: &arrow::datatypes::Schema,
+    frame: &polars::prelude::DataFrame,
+) -> Vec<NullViolation> {
+    let mut violations = vec![];
+    for field in schema.fields().iter() {
+        let Some(nulls) = field_nulls(field, frame) else {
+            continue;
+        };
+        let null_count = nulls.sum().unwrap_or(0) as usize;
+        if null_count == 0 {
+            continue;
+        }
+        let first_rows = nulls
+            .into_iter()
+            .enumerate()
+            .filter_map(|(idx, x)| (x == Some(true)).then_some(idx))
+            .take(ROWS_REPORTED)
+            .collect();
+        violations.push(NullViolation {
+            column: field.name().to_owned(),
+            null_count,
+            first_rows,
+        });
+    }
+    violations
+}
+
+/// Replaces the nulls of the member at `path` in the struct `series` with
+/// defaults. The structs along the path lose their nulls, which pass down
+/// to their other members instead.
+fn fill_member_nulls(
+    series: &polars::prelude::Series,
+    path: &[&str],
+) -> InsResult<polars::prelude::Series> {
+    let polars_error =
+        |e: polars::prelude::PolarsError| InsError::ConvertError("null policy", e.to_string());
+    let Some((segment, rest)) = path.split_first() else {
+        let column = series.clone().into_column();
+        let filled = column
+            .zip_with(&column.is_not_null(), &column_default(&column)?)
+            .map_err(polars_error)?;
+        return Ok(filled.take_materialized_series());
+    };
+    let parent = series.struct_().map_err(polars_error)?;
+    let is_valid = series.is_not_null();
+    let mut members = vec![];
+    for member in parent.fields_as_series().iter() {
+        let member = if series.null_count() == 0 {
+            member.clone()
+        } else {
+            let nulls = polars::prelude::Series::full_null(
+                member.name().clone(),
+                member.len(),
+                member.dtype(),
+            );
+            member.zip_with(&is_valid, &nulls).map_err(polars_error)?
+        };
+        members.push(if member.name().as_str() == *segment {
+            fill_member_nulls(&member, rest)?
+        } else {
+            member
+        });
+    }
+    polars::prelude::StructChunked::from_series(series.name().clone(), series.len(), members.iter())
+        .map(|x| x.into_series())
+        .map_err(polars_error)
+}
+
+fn apply_null_policy(
+    schema: &arrow::datatypes::Schema,
+    policy: NullPolicy,
+    mut frame: polars::prelude::DataFrame,
+) -> InsResult<polars::prelude::DataFrame> {
+    let violations = null_violations(schema, &frame);
+    if violations.is_empty() {
+        return Ok(frame);
+    }
+    let polars_error =
+        |e: polars::prelude::PolarsError| InsError::ConvertError("null policy", e.to_string());
+    let violating = violations
+        .iter()
+        .map(|x| x.column.as_str())
+        .collect::<HashSet<_>>();
+    let fields = schema
+        .fields()
+        .iter()
+        .filter(|x| violating.contains(x.name().as_str()));
+    match policy {
+        NullPolicy::Reject => Err(InsError::NullError(
+            violations
+                .iter()
+                .map(|x| {
+                    format!(
+                        "{} ({} null(s), first at rows {:?})",
+                        x.column, x.null_count, x.first_rows
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+        )),
+        NullPolicy::FillDefault => {
+            for field in fields {
+                let path = source_path(field);
+                let name = path.as_ref().map(|x| x[0]).unwrap_or(field.name());
+                let series = frame.column(name).map_err(polars_error)?;
+                let filled = fill_member_nulls(
+                    series.as_materialized_series(),
+                    path.as_ref().map(|x| &x[1..]).unwrap_or_default(),
+                )?;
+                frame.with_column(filled).map_err(polars_error)?;
+            }
+            Ok(frame)
+        }
+        NullPolicy::DropRows => {
+            let mut mask = polars::prelude::BooleanChunked::full("".into(), true, frame.height());
+            for field in fields {
+                if let Some(nulls) = field_nulls(field, &frame) {
+                    mask = &mask & &!nulls;
+                }
+            }
+            frame.filter(&mask).map_err(polars_error)
+        }
+    }
+}
+
+fn column_default(column: &PlColumn) -> InsResult<PlColumn> {
+    let name = column.name().clone();
+    let value = match column.dtype() {
+        PlDtype::String | PlDtype::Categorical(..) => polars::prelude::Series::new(name, [""]),
+        PlDtype::Binary => polars::prelude::Series::new(name, [&b""[..]]),
+        PlDtype::Enum(Some(revmap), _) => {
+            polars::prelude::Series::new(name, [revmap.get_categories().value(0)])
+        }
+        _ => polars::prelude::Series::new(name, [0i32]),
+    };
+    match value.cast(column.dtype()) {
+        Ok(x) => Ok(x.into_column().new_from_index(0, column.len())),
+        Err(e) => Err(InsError::ConvertError("column default", e.to_string())),
+    }
+}
+
+/// Bounds in the physical unit of a Date or Datetime column for a range of
+/// seconds since the epoch.
+fn physical_bounds(dtype: &PlDtype, (min, max): (i64, i64)) -> Option<(i64, i64)> {
+    const DAY: i64 = 86_400;
+    let per_second = match dtype {
+        PlDtype::Date => return Some((-(-min).div_euclid(DAY), max.div_euclid(DAY))),
+        PlDtype::Datetime(polars::prelude::TimeUnit::Milliseconds, _) => 1_000,
+        PlDtype::Datetime(polars::prelude::TimeUnit::Microseconds, _) => 1_000_000,
+        PlDtype::Datetime(polars::prelude::TimeUnit::Nanoseconds, _) => 1_000_000_000,
+        _ => return None,
+    };
+    Some((
+        min.saturating_mul(per_second),
+        max.saturating_mul(per_second)
+            .saturating_add(per_second - 1),
+    ))
+}
+
+/// Bounds in the unit of the arrow type `target` a ClickHouse temporal
+/// column is encoded as.
+fn target_bounds(target: &ArDtype, range: (i64, i64)) -> Option<(i64, i64)> {
+    let dtype = match target {
+        ArDtype::Date32 => PlDtype::Date,
+        ArDtype::Timestamp(arrow::datatypes::TimeUnit::Second, _) => return Some(range),
+        ArDtype::Timestamp(tu, _) => PlDtype::Datetime(polars_time_unit(tu), None),
+        _ => return None,
+    };
+    physical_bounds(&dtype, range)
+}
+
+fn polars_time_unit(tu: &arrow::datatypes::TimeUnit) -> polars::prelude::TimeUnit {
+    match tu {
+        arrow::datatypes::TimeUnit::Second | arrow::datatypes::TimeUnit::Millisecond => {
+            polars::prelude::TimeUnit::Milliseconds
+        }
+        arrow::datatypes::TimeUnit::Microsecond => polars::prelude::TimeUnit::Microseconds,
+        arrow::datatypes::TimeUnit::Nanosecond => polars::prelude::TimeUnit::Nanoseconds,
+    }
+}
+
+/// The values of `column` checked against the range of `ch_type`, with their
+/// bounds. Strings cast to the table are parsed first, unless some do not
+/// parse and are left for the encoder to report, and integers count the unit
+/// the column is encoded in.
+fn range_values(column: &PlColumn, ch_type: &str) -> Option<(PlColumn, (i64, i64))> {
+    let range = clickhouse_temporal_range(ch_type)?;
+    let target = clickhouse_to_arrow_datatype(ch_type).ok()?;
+    match column.dtype() {
+        PlDtype::Date | PlDtype::Datetime(..) => {
+            Some((column.clone(), physical_bounds(column.dtype(), range)?))
+        }
+        PlDtype::String => {
+            let dtype = match &target {
+                ArDtype::Date32 => PlDtype::Date,
+                ArDtype::Timestamp(tu, _) => PlDtype::Datetime(polars_time_unit(tu), None),
+                _ => return None,
+            };
+            let parsed = column.cast(&dtype).ok()?;
+            if parsed.null_count() != column.null_count() {
+                return None;
+            }
+            let bounds = physical_bounds(parsed.dtype(), range)?;
+            Some((parsed, bounds))
+        }
+        x if x.is_integer() => Some((column.clone(), target_bounds(&target, range)?)),
+        _ => None,
+    }
+}
+
+fn physical_values(column: &PlColumn) -> InsResult<polars::prelude::Int64Chunked> {
+    match column.to_physical_repr().cast(&PlDtype::Int64) {
+        Ok(x) => match x.i64() {
+            Ok(v) => Ok(v.clone()),
+            Err(e) => Err(InsError::ConvertError("temporal values", e.to_string())),
+        },
+        Err(e) => Err(InsError::ConvertError("temporal values", e.to_string())),
+    }
+}
+
+/// Values of the `(column, ClickHouse type)` pairs in `temporal` outside the
+/// range of the type, see [`range_values`].
+fn range_violations(
+    temporal: &[(String, String)],
+    frame: &polars::prelude::DataFrame,
+) -> InsResult<Vec<RangeViolation>> {
+    let mut violations = vec![];
+    for (name, ch_type) in temporal.iter() {
+        let Ok(column) = frame.column(name) else {
+            continue;
+        };
+        let Some((values, (min, max))) = range_values(column, ch_type) else {
+            continue;
+        };
+        let values = physical_values(&values)?;
+        let outside = values
+            .into_iter()
+            .enumerate()
+            .filter_map(|(idx, x)| x.is_some_and(|v| v < min || v > max).then_some(idx))
+            .collect::<Vec<_>>();
+        if !outside.is_empty() {
+            violations.push(RangeViolation {
+                column: name.clone(),
+                ch_type: ch_type.clone(),
+                count: outside.len(),
+                first_rows: outside.into_iter().take(ROWS_REPORTED).collect(),
+            });
+        }
+    }
+    Ok(violations)
+}
+
+fn apply_range_policy(
+    temporal: &[(String, String)],
+    policy: RangePolicy,
+    mut frame: polars::prelude::DataFrame,
+) -> InsResult<polars::prelude::DataFrame> {
+    let violations = range_violations(temporal, &frame)?;
+    if violations.is_empty() {
+        return Ok(frame);
+    }
+    if policy == RangePolicy::Reject {
+        return Err(InsError::RangeError(
+            violations
+                .iter()
+                .map(|x| {
+                    format!(
+                        "{} as {} ({} value(s), first at rows {:?})",
+                        x.column, x.ch_type, x.count, x.first_rows
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+        ));
+    }
+    for violation in violations.iter() {
+        let column = match frame.column(&violation.column) {
+            Ok(x) => x,
+            Err(e) => return Err(InsError::ConvertError("range policy", e.to_string())),
+        };
+        let Some((values, (min, max))) = range_values(column, &violation.ch_type) else {
+            continue;
+        };
+        let replaced: polars::prelude::Int64Chunked = physical_values(&values)?
+            .into_iter()
+            .map(|x| match (x, policy) {
+                (Some(v), RangePolicy::Null) if v < min || v > max => None,
+                (x, _) => x.map(|v| v.clamp(min, max)),
+            })
+            .collect();
+        let replaced = replaced
+            .with_name(column.name().clone())
+            .into_column()
+            .cast(values.dtype());
+        let result = replaced.and_then(|x| frame.with_column(x).map(|_| ()));
+        if let Err(e) = result {
+            return Err(InsError::ConvertError("range policy", e.to_string()));
+        }
+    }
+    Ok(frame)
+}
+
+/// Checks and fixes applied to every frame before its body is encoded.
+#[derive(Clone)]
+struct FrameChecks {
+    schema: Arc<arrow::datatypes::Schema>,
+    null_policy: NullPolicy,
+    range_policy: RangePolicy,
+    temporal: Vec<(String, String)>,
+}
+
+impl FrameChecks {
+    fn apply(&self, frame: polars::prelude::DataFrame) -> InsResult<polars::prelude::DataFrame> {
+        // nulls left by RangePolicy::Null are then seen by the null policy
+        let frame = apply_range_policy(&self.temporal, self.range_policy, frame)?;
+        apply_null_policy(&self.schema, self.null_policy, frame)
+    }
+}
+
+/// Widest precision of the narrowest `DecimalN` holding `precision` digits,
+/// i.e. 9, 18, 38 or 76 for Decimal32 to Decimal256.
+fn clickhouse_decimal_precision(precision: usize, scale: usize) -> InsResult<u8> {
+    if precision == 0 || scale > precision {
+        return Err(InsError::BuildError(
+            "clickhouse decimal",
+            format!("Decimal({}, {}) has no valid precision", precision, scale),
+        ));
+    }
+    match precision {
+        1..=9 => Ok(9),
+        10..=18 => Ok(18),
+        19..=38 => Ok(38),
+        39..=76 => Ok(76),
+        _ => Err(InsError::BuildError(
+            "clickhouse decimal",
+            format!("Decimal({}, {}) exceeds 76 digits", precision, scale),
+        )),
+    }
+}
+
+/// Digits needed for every value of a decimal column, and at least its scale.
+fn inferred_decimal_precision(column: &PlColumn, scale: usize) -> usize {
+    let digits = |v: i128| {
+        v.unsigned_abs()
+            .checked_ilog10()
+            .map_or(1, |d| d as usize + 1)
+    };
+    match column.decimal() {
+        Ok(x) => x
+            .physical()
+            .into_iter()
+            .flatten()
+            .map(digits)
+            .fold(scale.max(1), usize::max),
+        // polars exports an unspecified precision as 38
+        Err(_) => 38,
+    }
+}
+
+/// Arrow type sent in the body for a column of arrow type `adt`, after the
+/// mappings chosen in `opts` are applied.
+pub fn clickhouse_arrow_datatype(adt: &ArDtype, opts: &TypeOptions) -> ArDtype {
+    let map_field = |f: &ArField| {
+        let dtype = match f.data_type() {
+            // only top-level columns get their own dictionary in the stream
+            ArDtype::Dictionary(_, v) => v.as_ref(),
+            x => x,
+        };
+        let dtype = clickhouse_arrow_datatype(dtype, opts);
+        let is_nullable = is_nullable_arrow(f.is_nullable(), &dtype, opts);
+        Arc::new(ArField::new(f.name(), dtype, is_nullable))
+    };
+    match adt {
+        ArDtype::Decimal128(p, s) | ArDtype::Decimal256(p, s) => {
+            match clickhouse_decimal_precision(*p as usize, (*s).max(0) as usize) {
+                Ok(76) => ArDtype::Decimal256(76, *s),
+                Ok(x) => ArDtype::Decimal128(x, *s),
+                Err(_) => adt.clone(),
+            }
+        }
+        ArDtype::Time32(_) | ArDtype::Time64(_) => match opts.time {
+            TimeMapping::Time64 => adt.clone(),
+            TimeMapping::SinceMidnight if matches!(adt, ArDtype::Time64(_)) => ArDtype::Int64,
+            TimeMapping::SinceMidnight => ArDtype::Int32,
+            TimeMapping::String => ArDtype::Binary,
+        },
+        ArDtype::Duration(_) => ArDtype::Int64,
+        ArDtype::List(f) => ArDtype::List(map_field(f)),
+        ArDtype::LargeList(f) => ArDtype::LargeList(map_field(f)),
+        ArDtype::FixedSizeList(f, sz) => ArDtype::FixedSizeList(map_field(f), *sz),
+        ArDtype::Map(f, sorted) => ArDtype::Map(map_field(f), *sorted),
+        ArDtype::Struct(fs) => {
+            ArDtype::Struct(ArFields::from_iter(fs.iter().map(|f| map_field(f))))
+        }
+        x => x.clone(),
+    }
+}
+
+pub fn polars_to_clickhouse_sql(pl: &PlArrowDtype, opts: &TypeOptions) -> InsResult<String> {
     Ok(match pl {
         PlArrowDtype::Null => "Null".to_owned(),
         PlArrowDtype::Boolean => "Bool".to_owned(),
@@ -35,49 +717,86 @@ fn polars_to_clickhouse_sql(pl: &PlArrowDtype) -> InsResult<String> {
         PlArrowDtype::UInt16 => "UInt16".to_owned(),
         PlArrowDtype::UInt32 => "UInt32".to_owned(),
         PlArrowDtype::UInt64 => "UInt64".to_owned(),
-        PlArrowDtype::Decimal(p, s) => format!("Decimal({}, {})", p, s),
-        PlArrowDtype::Decimal256(p, s) => format!("Decimal({}, {})", p, s),
+        PlArrowDtype::Decimal(p, s) | PlArrowDtype::Decimal256(p, s) => {
+            format!("Decimal({}, {})", clickhouse_decimal_precision(*p, *s)?, s)
+        }
         PlArrowDtype::Float32 => "Float32".to_owned(),
         PlArrowDtype::Float64 => "Float64".to_owned(),
         PlArrowDtype::Utf8 => "String".to_owned(),
         PlArrowDtype::Utf8View => "String".to_owned(),
-        PlArrowDtype::Date32 => "Date32".to_owned(),
+        PlArrowDtype::LargeUtf8 => "String".to_owned(),
+        PlArrowDtype::Binary => "String".to_owned(),
+        PlArrowDtype::LargeBinary => "String".to_owned(),
+        PlArrowDtype::BinaryView => "String".to_owned(),
+        PlArrowDtype::FixedSizeBinary(n) => format!("FixedString({})", n),
+        // categoricals, top-level ones are declared LowCardinality
+        PlArrowDtype::Dictionary(..) => "String".to_owned(),
+        PlArrowDtype::Date32 => match opts.dates {
+            DateMapping::Wide => "Date32".to_owned(),
+            DateMapping::Narrow => "Date".to_owned(),
+        },
         PlArrowDtype::Date64 => "DateTime".to_owned(),
-        PlArrowDtype::Timestamp(..) => "DateTime64".to_owned(),
-        PlArrowDtype::Time32(_) => "Timestamp".to_owned(),
-        PlArrowDtype::Time64(_) => "Timestamp".to_owned(),
+        PlArrowDtype::Timestamp(tu, tz) => clickhouse_datetime(tu, tz.as_deref()),
+        PlArrowDtype::Time32(tu) => clickhouse_time(tu, false, opts.time),
+        PlArrowDtype::Time64(tu) => clickhouse_time(tu, true, opts.time),
+        // Interval types cannot be stored, top-level columns note the unit
+        // in their comment instead
+        PlArrowDtype::Duration(_) => "Int64".to_owned(),
         PlArrowDtype::List(ll) => {
-            if ll.is_nullable && !ll.dtype().is_nested() {
-                format!("Array(Nullable({}))", polars_to_clickhouse_sql(ll.dtype())?)
+            if is_nullable_member(ll.is_nullable, ll.dtype(), opts) {
+                format!(
+                    "Array(Nullable({}))",
+                    polars_to_clickhouse_sql(ll.dtype(), opts)?
+                )
             } else {
-                format!("Array({})", polars_to_clickhouse_sql(ll.dtype())?)
+                format!("Array({})", polars_to_clickhouse_sql(ll.dtype(), opts)?)
             }
         }
         PlArrowDtype::LargeList(ll) => {
-            if ll.is_nullable && !ll.dtype().is_nested() {
-                format!("Array(Nullable({}))", polars_to_clickhouse_sql(ll.dtype())?)
+            if is_nullable_member(ll.is_nullable, ll.dtype(), opts) {
+                format!(
+                    "Array(Nullable({}))",
+                    polars_to_clickhouse_sql(ll.dtype(), opts)?
+                )
             } else {
-                format!("Array({})", polars_to_clickhouse_sql(ll.dtype())?)
+                format!("Array({})", polars_to_clickhouse_sql(ll.dtype(), opts)?)
             }
         }
         PlArrowDtype::FixedSizeList(ll, _) => {
-            if ll.is_nullable && !ll.dtype().is_nested() {
-                format!("Array(Nullable({}))", polars_to_clickhouse_sql(ll.dtype())?)
+            if is_nullable_member(ll.is_nullable, ll.dtype(), opts) {
+                format!(
+                    "Array(Nullable({}))",
+                    polars_to_clickhouse_sql(ll.dtype(), opts)?
+                )
             } else {
-                format!("Array({})", polars_to_clickhouse_sql(ll.dtype())?)
+                format!("Array({})", polars_to_clickhouse_sql(ll.dtype(), opts)?)
             }
         }
-        PlArrowDtype::Struct(ls) => {
-            let mut new_types = vec![];
-            for ll in ls {
-                new_types.push(if ll.is_nullable && !ll.dtype().is_nested() {
-                    format!("Nullable({})", polars_to_clickhouse_sql(ll.dtype())?)
+        PlArrowDtype::Struct(ls) => format!("Tuple({})", clickhouse_named_fields(ls, opts)?),
+        PlArrowDtype::Map(entries, _) => match entries.dtype() {
+            PlArrowDtype::Struct(kv) if kv.len() == 2 => {
+                let value = &kv[1];
+                let value_type = if is_nullable_member(value.is_nullable, value.dtype(), opts) {
+                    format!(
+                        "Nullable({})",
+                        polars_to_clickhouse_sql(value.dtype(), opts)?
+                    )
                 } else {
-                    polars_to_clickhouse_sql(ll.dtype())?
-                });
+                    polars_to_clickhouse_sql(value.dtype(), opts)?
+                };
+                format!(
+                    "Map({}, {})",
+                    polars_to_clickhouse_sql(kv[0].dtype(), opts)?,
+                    value_type
+                )
             }
-            format!("Tuple({})", new_types.join(","))
-        }
+            x => {
+                return Err(InsError::ConversionNotImplementedError(format!(
+                    "map entries must be a key, value struct: {:?}",
+                    x
+                )));
+            }
+        },
         x => {
             return Err(InsError::ConversionNotImplementedError(format!(
                 "not a Clickhouse SQL DataType: {:?}",
@@ -87,70 +806,391 @@ fn polars_to_clickhouse_sql(pl: &PlArrowDtype) -> InsResult<String> {
     })
 }
 
-impl CreateCmd for ClickhouseInserter {}
+/// Reinterprets a `List(Struct{key, value})` column as a map, taking the
+/// struct's first field as the key and its second as the value.
+fn polars_list_to_map(name: &str, pl: &PlArrowDtype) -> InsResult<PlArrowDtype> {
+    let kv = match pl {
+        PlArrowDtype::List(f) | PlArrowDtype::LargeList(f) => match f.dtype() {
+            PlArrowDtype::Struct(kv) if kv.len() == 2 => kv,
+            _ => &vec![],
+        },
+        _ => &vec![],
+    };
+    if kv.is_empty() {
+        return Err(InsError::BuildError(
+            "clickhouse map",
+            format!("{} is not a list of two-field structs", name),
+        ));
+    }
+    let entries = PlArrowDtype::Struct(vec![
+        PlArrowField::new("key".into(), kv[0].dtype().clone(), false),
+        PlArrowField::new("value".into(), kv[1].dtype().clone(), kv[1].is_nullable),
+    ]);
+    Ok(PlArrowDtype::Map(
+        Box::new(PlArrowField::new("entries".into(), entries, false)),
+        false,
+    ))
+}
+
+/// Expands a struct column into one column per leaf field, keeping structs
+/// below the depth limit whole. `path` joins the source names with
+/// [`SOURCE_PATH_SEPARATOR`].
+fn flatten_struct(
+    name: &str,
+    path: &str,
+    dtype: &PlDtype,
+    depth: usize,
+    opts: &TypeOptions,
+    out: &mut Vec<(String, PlDtype, Option<String>)>,
+) {
+    let children = match dtype {
+        PlDtype::Struct(x) if opts.flatten_depth.is_none_or(|d| depth < d) => x,
+        _ => {
+            let path = (depth > 0).then(|| path.to_owned());
+            out.push((name.to_owned(), dtype.clone(), path));
+            return;
+        }
+    };
+    for child in children {
+        flatten_struct(
+            &format!("{}{}{}", name, opts.flatten_separator, child.name()),
+            &format!("{}{}{}", path, SOURCE_PATH_SEPARATOR, child.name()),
+            child.dtype(),
+            depth + 1,
+            opts,
+            out,
+        );
+    }
+}
+
+/// `name T, ...` members of a named Tuple or Nested type.
+fn clickhouse_named_fields(fields: &[PlArrowField], opts: &TypeOptions) -> InsResult<String> {
+    let mut members = vec![];
+    for f in fields {
+        let typename = if is_nullable_member(f.is_nullable, f.dtype(), opts) {
+            format!("Nullable({})", polars_to_clickhouse_sql(f.dtype(), opts)?)
+        } else {
+            polars_to_clickhouse_sql(f.dtype(), opts)?
+        };
+        members.push(format!("{} {}", clickhouse_identifier(&f.name), typename));
+    }
+    Ok(members.join(", "))
+}
+
+fn clickhouse_identifier(name: &str) -> String {
+    format!("`{}`", name.replace('\\', "\\\\").replace('`', "\\`"))
+}
+
+impl CreateCmd for ClickhouseInserter {
+    fn identifier(name: &str) -> String {
+        clickhouse_identifier(name)
+    }
+}
+
+impl Inserter for ClickhouseInserter {
+    fn spec(&self) -> &TableSpec {
+        &self.spec
+    }
+
+    fn spec_mut(&mut self) -> &mut TableSpec {
+        &mut self.spec
+    }
+
+    fn sql_type(&self, dtype: &PlArrowDtype) -> InsResult<String> {
+        polars_to_clickhouse_sql(dtype, &self.type_options)
+    }
+
+    fn build_queries(mut self) -> InsResult<Self> {
+        let table_name = self.get_table_name();
+        let engine = self
+            .engine
+            .as_ref()
+            .map(|x| format!("Engine = {}", x))
+            .unwrap_or_default();
+        self.validate_key_columns()?;
+        let order_by = self.key_clause("ORDER BY", &self.spec.order_by);
+        let partition_by = self.key_clause("PARTITION BY", &self.partition_by);
+        let primary_key = self.key_clause("PRIMARY KEY", &self.spec.primary_key);
+        let sample_by = self.key_clause("SAMPLE BY", self.sample_by.as_slice());
+        let ttl = if self.ttl.is_empty() {
+            String::new()
+        } else {
+            let rules = self
+                .ttl
+                .iter()
+                .map(|(column, interval)| format!("{} + {}", Self::identifier(column), interval))
+                .collect::<Vec<_>>();
+            format!("TTL {}", rules.join(", "))
+        };
+        let settings = if self.table_settings.is_empty() {
+            String::new()
+        } else {
+            let pairs = self
+                .table_settings
+                .iter()
+                .map(|(k, v)| format!("{} = {}", k, v))
+                .collect::<Vec<_>>();
+            format!("SETTINGS {}", pairs.join(", "))
+        };
+        let table_config = [
+            engine,
+            order_by,
+            partition_by,
+            primary_key,
+            sample_by,
+            ttl,
+            settings,
+        ]
+        .into_iter()
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+        let mut fields = vec![];
+        for (name, pladt) in self.spec.fields.iter() {
+            let (is_nullable, typename) = self.column_type(name, pladt.as_ref())?;
+            fields.push(self.column_definition(name, is_nullable, typename.as_deref())?);
+        }
+        self.cached_alter_queries = self.alter_queries(&table_name)?;
+        self.cached_create_query = Some(Self::table(
+            table_name.as_str(),
+            fields.as_slice(),
+            table_config.as_str(),
+            self.spec.override_creation.as_deref(),
+        ));
+        self.cached_insert_query = Some(Self::insert(table_name.as_str()));
+        Ok(self)
+    }
+
+    fn get_create_query(&self) -> InsResult<&str> {
+        match self.cached_create_query.as_deref() {
+            Some(x) => Ok(x),
+            None => Err(InsError::BuildError(
+                "clickhouse create_query",
+                "not yet built, first run self.build_create_query".to_owned(),
+            )),
+        }
+    }
+
+    /// Decimal columns without a precision get the one their values need.
+    fn with_schema_from_cols(self, columns: &[PlColumn]) -> InsResult<Self> {
+        let dtypes = columns
+            .iter()
+            .map(|c| match c.dtype() {
+                PlDtype::Decimal(None, scale) => {
+                    let precision = inferred_decimal_precision(c, scale.unwrap_or_default());
+                    PlDtype::Decimal(Some(precision), *scale)
+                }
+                x => x.clone(),
+            })
+            .collect::<Vec<_>>();
+        // narrow dates that do not fit are widened to Date32
+        let mut widened = vec![];
+        if self.type_options.dates == DateMapping::Narrow {
+            for column in columns.iter().filter(|x| x.dtype() == &PlDtype::Date) {
+                let bounds = clickhouse_temporal_range("Date")
+                    .and_then(|range| physical_bounds(column.dtype(), range));
+                let Some((min, max)) = bounds else {
+                    continue;
+                };
+                let values = physical_values(column)?;
+                if values.into_iter().flatten().any(|v| v < min || v > max) {
+                    widened.push(column.name().to_string());
+                }
+            }
+        }
+        let mut ins = self.with_schema_from_dtypes(
+            columns
+                .iter()
+                .zip(dtypes.iter())
+                .map(|(c, dtype)| (c.name().as_str(), dtype)),
+        )?;
+        for name in widened {
+            ins.declared_types.insert(name, "Date32".to_owned());
+        }
+        Ok(ins)
+    }
+
+    fn get_insert_query(&self) -> InsResult<&str> {
+        match self.cached_insert_query.as_deref() {
+            Some(x) => Ok(x),
+            None => Err(InsError::BuildError(
+                "clickhouse insert_query",
+                "not yet built, first run self.build_queries".to_owned(),
+            )),
+        }
+    }
+
+    fn get_body(
+        &self,
+        frame: &polars::prelude::DataFrame,
+    ) -> InsResult<impl std::io::Read + Send + 'static> {
+        self.get_arrow_chunks(frame)
+    }
+}
 
 impl ClickhouseInserter {
     pub fn default(table: &str) -> Self {
         Self {
-            override_creation: None,
-            table_name: table.to_owned(),
-            db_name: None,
-            fields: HashMap::new(),
+            spec: TableSpec::new(table),
             engine: None,
-            order_by: vec![],
-            primary_key: vec![],
-            not_null: HashSet::new(),
-            override_fields: HashMap::new(),
+            partition_by: vec![],
+            sample_by: None,
+            ttl: vec![],
+            table_settings: vec![],
+            key_expressions: HashSet::new(),
+            map_columns: HashSet::new(),
+            column_options: HashMap::new(),
+            type_options: TypeOptions::default(),
+            table_description: None,
+            cast_to_table: false,
+            migration_policy: MigrationPolicy::default(),
+            null_policy: NullPolicy::default(),
+            range_policy: RangePolicy::default(),
+            max_batch_rows: DEFAULT_MAX_BATCH_ROWS,
+            declared_types: HashMap::new(),
             schema: Arc::new(arrow::datatypes::Schema::empty()),
             cached_create_query: None,
             cached_insert_query: None,
+            cached_alter_queries: vec![],
         }
     }
 
-    pub fn with_order_by(mut self, subkeys: Vec<String>) -> Self {
-        self.order_by.extend_from_slice(subkeys.as_slice());
-        self.not_null.extend(subkeys);
+    /// Like `with_order_by`, but the entries are SQL expressions such as
+    /// `toDate(ts)` and are emitted without quoting.
+    pub fn with_order_by_expr(mut self, exprs: Vec<String>) -> Self {
+        self.spec.order_by.extend_from_slice(exprs.as_slice());
+        self.key_expressions.extend(exprs);
+        self
+    }
+
+    pub fn with_primary_key_expr(mut self, exprs: Vec<String>) -> Self {
+        self.spec.primary_key.extend_from_slice(exprs.as_slice());
+        self.key_expressions.extend(exprs);
+        self
+    }
+
+    pub fn with_partition_by(mut self, subkeys: Vec<String>) -> Self {
+        self.partition_by.extend(subkeys);
+        self
+    }
+
+    pub fn with_partition_by_expr(mut self, exprs: Vec<String>) -> Self {
+        self.partition_by.extend_from_slice(exprs.as_slice());
+        self.key_expressions.extend(exprs);
+        self
+    }
+
+    pub fn with_sample_by(mut self, column: &str) -> Self {
+        let _ = self.sample_by.insert(column.to_owned());
+        self
+    }
+
+    pub fn with_sample_by_expr(mut self, expr: &str) -> Self {
+        let _ = self.sample_by.insert(expr.to_owned());
+        self.key_expressions.insert(expr.to_owned());
+        self
+    }
+
+    /// Adds a `TTL column + interval` rule; `interval` may carry the rule's
+    /// action, e.g. `INTERVAL 1 MONTH DELETE`.
+    pub fn with_ttl(mut self, column: &str, interval: &str) -> Self {
+        self.ttl.push((column.to_owned(), interval.to_owned()));
+        self
+    }
+
+    pub fn with_table_settings(mut self, key: &str, value: &str) -> Self {
+        self.table_settings.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn with_time_mapping(mut self, mapping: TimeMapping) -> Self {
+        self.type_options.time = mapping;
         self
     }
 
-    pub fn replace_order_by(mut self, subkeys: Vec<String>) -> Self {
-        self.order_by.extend_from_slice(subkeys.as_slice());
-        self.not_null.extend(subkeys);
+    pub fn with_nested_nulls(mut self, policy: NestedNulls) -> Self {
+        self.type_options.nested_nulls = policy;
         self
     }
 
-    pub fn with_not_null(mut self, subkeys: Vec<String>) -> Self {
-        self.not_null.extend(subkeys);
+    pub fn with_struct_mapping(mut self, mapping: StructMapping) -> Self {
+        self.type_options.structs = mapping;
         self
     }
 
-    pub fn replace_not_null(mut self, subkeys: Vec<String>) -> Self {
-        self.not_null.clear();
-        self.not_null.extend(subkeys);
+    /// Flatten struct columns into `parent{separator}child` columns, down to
+    /// `max_depth` levels of nesting or all of them if `None`.
+    pub fn with_flatten(mut self, separator: &str, max_depth: Option<usize>) -> Self {
+        self.type_options.structs = StructMapping::Flatten;
+        self.type_options.flatten_separator = separator.to_owned();
+        self.type_options.flatten_depth = max_depth;
         self
     }
 
-    pub fn with_primary_key(mut self, subkeys: Vec<String>) -> Self {
-        self.primary_key.extend_from_slice(subkeys.as_slice());
-        self.not_null.extend(subkeys);
+    fn column_options_mut(&mut self, column: &str) -> &mut ColumnOptions {
+        self.column_options.entry(column.to_owned()).or_default()
+    }
+
+    /// Declare a String or Binary column as `FixedString(length)`; shorter
+    /// values are zero-padded and longer ones fail the encoding.
+    pub fn with_fixed_string(mut self, column: &str, length: usize) -> Self {
+        self.column_options_mut(column).fixed_string = Some(length);
+        self
+    }
+
+    /// Declare a String or Binary column as `UUID`, `IPv4` or `IPv6`. String
+    /// values are parsed from their text form, binary ones must hold the
+    /// 16 (or 4 for IPv4) raw bytes.
+    pub fn with_semantic_type(mut self, column: &str, semantic: Semantic) -> Self {
+        self.column_options_mut(column).semantic = Some(semantic);
+        self
+    }
+
+    pub fn with_codec(mut self, column: &str, codecs: Vec<String>) -> Self {
+        self.column_options_mut(column).codec = codecs;
+        self
+    }
+
+    fn with_column_default(mut self, column: &str, default: ColumnDefault) -> Self {
+        let _ = self.column_options_mut(column).default.insert(default);
+        if !self.spec.fields.iter().any(|(name, _)| name == column) {
+            self.spec.set_field(column, None);
+        }
         self
     }
 
-    pub fn replace_primary_key(mut self, subkeys: Vec<String>) -> Self {
-        self.primary_key.extend_from_slice(subkeys.as_slice());
-        self.not_null.extend(subkeys);
+    /// Columns that are not in the frame are appended to the table with the
+    /// type ClickHouse infers from `expr`.
+    pub fn with_default(self, column: &str, expr: &str) -> Self {
+        self.with_column_default(column, ColumnDefault::Default(expr.to_owned()))
+    }
+
+    pub fn with_materialized(self, column: &str, expr: &str) -> Self {
+        self.with_column_default(column, ColumnDefault::Materialized(expr.to_owned()))
+    }
+
+    pub fn with_alias(self, column: &str, expr: &str) -> Self {
+        self.with_column_default(column, ColumnDefault::Alias(expr.to_owned()))
+    }
+
+    pub fn with_comment(mut self, column: &str, comment: &str) -> Self {
+        let _ = self
+            .column_options_mut(column)
+            .comment
+            .insert(comment.to_owned());
         self
     }
 
-    pub fn with_field(mut self, column: &str, constraint: &str) -> Self {
-        self.fields.insert(column.to_owned(), None);
-        self.override_fields
-            .insert(column.to_owned(), constraint.to_owned());
+    /// Treat these `List(Struct{key, value})` columns as `Map(K, V)`.
+    pub fn with_map(mut self, columns: Vec<String>) -> Self {
+        self.map_columns.extend(columns);
         self
     }
 
-    pub fn with_dbname(mut self, db_name: &str) -> Self {
-        let _ = self.db_name.insert(db_name.to_owned());
+    pub fn with_low_cardinality(mut self, columns: Vec<String>) -> Self {
+        for column in columns {
+            self.column_options_mut(&column).low_cardinality = true;
+        }
         self
     }
 
@@ -159,104 +1199,510 @@ impl ClickhouseInserter {
         self
     }
 
-    pub fn with_create_method(mut self, override_creation: &str) -> Self {
-        let _ = self.override_creation.insert(override_creation.to_owned());
+    /// Rows per record batch of the encoded bodies, bounding the memory an
+    /// insert holds on top of the frame.
+    pub fn with_max_batch_rows(mut self, rows: usize) -> Self {
+        self.max_batch_rows = rows;
         self
     }
 
-    pub fn with_table_name(mut self, table_name: &str) -> Self {
-        self.table_name = table_name.to_owned();
+    pub fn with_migration_policy(mut self, policy: MigrationPolicy) -> Self {
+        self.migration_policy = policy;
         self
     }
 
-    pub fn build_queries(mut self) -> InsResult<Self> {
-        let table_name = if let Some(x) = self.db_name.as_ref() {
-            format!("{}.{}", x, self.table_name)
+    fn key_clause(&self, clause: &str, keys: &[String]) -> String {
+        let keys = keys
+            .iter()
+            .map(|x| {
+                if self.key_expressions.contains(x) {
+                    x.to_owned()
+                } else {
+                    Self::identifier(x)
+                }
+            })
+            .collect::<Vec<_>>();
+        match keys.len() {
+            0 => String::new(),
+            1 => format!("{} {}", clause, keys[0]),
+            _ => format!("{} ({})", clause, keys.join(", ")),
+        }
+    }
+
+    fn column_type(
+        &self,
+        name: &str,
+        pladt: Option<&PlArrowDtype>,
+    ) -> InsResult<(bool, Option<String>)> {
+        let is_nullable = pladt
+            .map(|x| is_nullable_member(true, x, &self.type_options))
+            .unwrap_or(false);
+        let typename = if let Some(t) = pladt.and(self.declared_types.get(name)) {
+            Some(t.clone())
+        } else if let Some(PlArrowDtype::List(f) | PlArrowDtype::LargeList(f)) = pladt
+            && let PlArrowDtype::Struct(fs) = f.dtype()
+            && self.type_options.structs == StructMapping::Nested
+        {
+            Some(format!(
+                "Nested({})",
+                clickhouse_named_fields(fs, &self.type_options)?
+            ))
+        } else if let Some(pl) = pladt {
+            Some(polars_to_clickhouse_sql(pl, &self.type_options)?)
         } else {
-            self.table_name.clone()
+            None
         };
-        let engine = self
-            .engine
-            .as_ref()
-            .map(|x| format!("Engine = {}", x))
-            .unwrap_or_default();
-        let order_by = if self.order_by.is_empty() {
-            String::new()
-        } else if self.order_by.len() == 1 {
-            format!("ORDER BY {}", self.order_by.first().unwrap())
-        } else {
-            format!("ORDER BY ({})", self.order_by.join(", "))
+        Ok((!self.spec.not_null.contains(name) && is_nullable, typename))
+    }
+
+    /// ADD COLUMN statements for the columns missing from the described
+    /// table, placed after their predecessor in the frame, and MODIFY COLUMN
+    /// statements for changed columns under [`MigrationPolicy::Permissive`].
+    fn alter_queries(&self, table_name: &str) -> InsResult<Vec<String>> {
+        let Some(table) = self.table_description.as_ref() else {
+            return Ok(vec![]);
         };
-        let primary_key = if self.primary_key.is_empty() {
-            String::new()
-        } else if self.primary_key.len() == 1 {
-            format!("PRIMARY KEY {}", self.primary_key.first().unwrap())
-        } else {
-            format!("PRIMARY KEY ({})", self.primary_key.join(", "))
+        let mut queries = vec![];
+        let mut previous: Option<&str> = None;
+        for (name, pladt) in self.spec.fields.iter() {
+            let (is_nullable, typename) = self.column_type(name, pladt.as_ref())?;
+            let definition = self.column_definition(name, is_nullable, typename.as_deref())?;
+            match (table.column(name), typename) {
+                (None, _) => {
+                    let position = match previous {
+                        Some(x) => format!("AFTER {}", Self::identifier(x)),
+                        None => "FIRST".to_owned(),
+                    };
+                    queries.push(format!(
+                        "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {}",
+                        table_name, definition, position
+                    ));
+                }
+                // overridden columns carry a free-form definition and are never modified
+                (Some(target), Some(t))
+                    if self.migration_policy == MigrationPolicy::Permissive
+                        && (!clickhouse_types_match(&t, &target.ch_type)
+                            || is_nullable != target.is_nullable()) =>
+                {
+                    queries.push(format!(
+                        "ALTER TABLE {} MODIFY COLUMN {}",
+                        table_name, definition
+                    ));
+                }
+                _ => {}
+            }
+            previous = Some(name);
+        }
+        Ok(queries)
+    }
+
+    fn column_definition(
+        &self,
+        name: &str,
+        is_nullable: bool,
+        typename: Option<&str>,
+    ) -> InsResult<String> {
+        // durations are stored as Int64, the comment records their unit
+        let unit_note = self
+            .spec
+            .fields
+            .iter()
+            .find(|(x, _)| x == name)
+            .and_then(|(_, dtype)| match dtype {
+                Some(PlArrowDtype::Duration(tu)) => Some(format!(
+                    "duration in {}",
+                    match tu {
+                        polars::prelude::ArrowTimeUnit::Second => "seconds",
+                        polars::prelude::ArrowTimeUnit::Millisecond => "milliseconds",
+                        polars::prelude::ArrowTimeUnit::Microsecond => "microseconds",
+                        polars::prelude::ArrowTimeUnit::Nanosecond => "nanoseconds",
+                    }
+                )),
+                _ => None,
+            });
+        let no_options = ColumnOptions::default();
+        let options = match self.column_options.get(name) {
+            Some(x) => x,
+            None if unit_note.is_some() => &no_options,
+            None => {
+                return Ok(Self::field(
+                    name,
+                    is_nullable,
+                    typename,
+                    self.spec.override_fields.get(name).map(|x| x.as_str()),
+                ));
+            }
         };
-        let table_config = format!("{} {} {}", engine, order_by, primary_key)
-            .trim()
-            .to_owned();
-        let mut fields = vec![];
-        for (name, pladt) in self.fields.iter() {
-            let is_nested = pladt.as_ref().map(|f| f.is_nested()).unwrap_or(true);
-            let typename = if let Some(pl) = &pladt {
-                Some(polars_to_clickhouse_sql(pl)?)
-            } else {
-                None
-            };
-            fields.push(Self::field(
-                name,
-                !self.not_null.contains(name) && !is_nested,
-                typename.as_deref(),
-                self.override_fields.get(name).map(|x| x.as_str()),
-            ));
+        let mut parts = match typename {
+            // LowCardinality(Nullable(T)) cannot take a NULL modifier
+            Some(t) if options.low_cardinality && is_nullable => {
+                vec![format!(
+                    "{} LowCardinality(Nullable({}))",
+                    Self::identifier(name),
+                    t
+                )]
+            }
+            Some(t) if options.low_cardinality => {
+                vec![format!(
+                    "{} LowCardinality({}) NOT NULL",
+                    Self::identifier(name),
+                    t
+                )]
+            }
+            _ => vec![
+                Self::field(
+                    name,
+                    is_nullable,
+                    typename,
+                    self.spec.override_fields.get(name).map(|x| x.as_str()),
+                )
+                .trim_end()
+                .to_owned(),
+            ],
+        };
+        match &options.default {
+            Some(ColumnDefault::Default(expr)) => parts.push(format!("DEFAULT {}", expr)),
+            Some(ColumnDefault::Materialized(expr) | ColumnDefault::Alias(expr))
+                if self.schema.field_with_name(name).is_ok() =>
+            {
+                return Err(InsError::BuildError(
+                    "clickhouse column",
+                    format!(
+                        "{} is computed by the server and cannot be inserted from the frame",
+                        name
+                    ),
+                ));
+            }
+            Some(ColumnDefault::Materialized(expr)) => parts.push(format!("MATERIALIZED {}", expr)),
+            Some(ColumnDefault::Alias(expr)) => parts.push(format!("ALIAS {}", expr)),
+            None => {}
         }
-        self.cached_create_query = Some(Self::table(
-            table_name.as_str(),
-            fields.as_slice(),
-            table_config.as_str(),
-            self.override_creation.as_deref(),
-        ));
-        self.cached_insert_query = Some(Self::insert(table_name.as_str()));
-        Ok(self)
+        let comment = match (&options.comment, unit_note) {
+            (Some(x), Some(note)) => Some(format!("{} ({})", x, note)),
+            (x, note) => x.clone().or(note),
+        };
+        if let Some(comment) = comment {
+            parts.push(format!("COMMENT {}", sql_string(&comment)));
+        }
+        if !options.codec.is_empty() {
+            parts.push(format!("CODEC({})", options.codec.join(", ")));
+        }
+        Ok(parts.join(" "))
     }
 
-    pub fn get_create_query(&self) -> InsResult<&str> {
-        match self.cached_create_query.as_deref() {
-            Some(x) => Ok(x),
-            None => Err(InsError::BuildError(
-                "clickhouse create_query",
-                "not yet built, first run self.build_create_query".to_owned(),
-            )),
+    fn validate_key_columns(&self) -> InsResult<()> {
+        let clauses = [
+            ("ORDER BY", self.spec.order_by.as_slice()),
+            ("PARTITION BY", self.partition_by.as_slice()),
+            ("PRIMARY KEY", self.spec.primary_key.as_slice()),
+            ("SAMPLE BY", self.sample_by.as_slice()),
+        ];
+        let ttl_columns = self.ttl.iter().map(|(column, _)| ("TTL", column));
+        let columns = clauses
+            .into_iter()
+            .flat_map(|(clause, keys)| keys.iter().map(move |k| (clause, k)))
+            .filter(|(_, k)| !self.key_expressions.contains(*k))
+            .chain(ttl_columns);
+        for (clause, column) in columns {
+            if !self.spec.fields.iter().any(|(name, _)| name == column) {
+                return Err(InsError::BuildError(
+                    "clickhouse table config",
+                    format!("{} references unknown column {}", clause, column),
+                ));
+            }
         }
+        Ok(())
+    }
+
+    #[cfg(feature = "lazy")]
+    pub fn with_schema_from_lazy(self, frame: &mut polars::prelude::LazyFrame) -> InsResult<Self> {
+        let schema = match frame.collect_schema() {
+            Ok(x) => x,
+            Err(e) => {
+                return Err(InsError::BuildError("lazy schema", e.to_string()));
+            }
+        };
+        self.with_schema_from_dtypes(schema.iter().map(|(name, dtype)| (name.as_str(), dtype)))
     }
 
-    pub fn with_schema_from_cols(mut self, columns: &[PlColumn]) -> InsResult<Self> {
+    fn with_schema_from_dtypes<'a>(
+        mut self,
+        columns: impl Iterator<Item = (&'a str, &'a PlDtype)>,
+    ) -> InsResult<Self> {
         let mut schema_builder = arrow::datatypes::SchemaBuilder::new();
-        for column in columns {
-            let pladt = column
-                .dtype()
-                .to_arrow(polars::prelude::CompatLevel::newest());
-            let adt = polars_to_arrow_datatype(&pladt)?;
-            let afield = ArField::new(
-                column.name().as_str(),
-                adt,
-                !self.not_null.contains(column.name().as_str()),
+        let mut fields = vec![];
+        let mut expanded = vec![];
+        for (name, dtype) in columns {
+            if self.type_options.structs == StructMapping::Flatten {
+                flatten_struct(name, name, dtype, 0, &self.type_options, &mut expanded);
+            } else {
+                expanded.push((name.to_owned(), dtype.clone(), None));
+            }
+        }
+        // joined names may collide with a column or another flattened field
+        let mut sources = HashMap::new();
+        for (name, _, path) in expanded.iter() {
+            let source = path
+                .as_deref()
+                .map(|x| x.replace(SOURCE_PATH_SEPARATOR, "."))
+                .unwrap_or_else(|| name.clone());
+            if let Some(first) = sources.insert(name.as_str(), source.clone()) {
+                return Err(InsError::BuildError(
+                    "clickhouse flatten",
+                    format!("{} and {} both map to column {}", first, source, name),
+                ));
+            }
+        }
+        for (name, dtype, path) in expanded.iter() {
+            let name = name.as_str();
+            match dtype {
+                PlDtype::Enum(Some(revmap), _) => {
+                    let categories = revmap.get_categories();
+                    let enum_type = clickhouse_enum(categories.values_iter())?;
+                    self.declared_types.insert(name.to_owned(), enum_type);
+                }
+                PlDtype::Enum(None, _) => {
+                    return Err(InsError::BuildError(
+                        "clickhouse enum",
+                        format!("categories of {} are unknown", name),
+                    ));
+                }
+                PlDtype::Categorical(..) => {
+                    let options = self.column_options.entry(name.to_owned()).or_default();
+                    options.low_cardinality = true;
+                }
+                _ => {}
+            }
+            let mut pladt = dtype.to_arrow(polars::prelude::CompatLevel::newest());
+            if self.map_columns.contains(name) {
+                pladt = polars_list_to_map(name, &pladt)?;
+            }
+            let options = self.column_options.get(name);
+            let is_string_like = matches!(
+                pladt,
+                PlArrowDtype::Utf8
+                    | PlArrowDtype::Utf8View
+                    | PlArrowDtype::LargeUtf8
+                    | PlArrowDtype::Binary
+                    | PlArrowDtype::BinaryView
+                    | PlArrowDtype::LargeBinary
             );
-            self.fields
-                .insert(column.name().to_string(), Some(pladt.to_owned()));
+            if let Some(length) = options.and_then(|x| x.fixed_string) {
+                if !is_string_like {
+                    return Err(InsError::BuildError(
+                        "clickhouse fixed string",
+                        format!("{} is not a string or binary column", name),
+                    ));
+                }
+                pladt = PlArrowDtype::FixedSizeBinary(length);
+            }
+            let table_column = self
+                .table_description
+                .as_ref()
+                .filter(|_| self.cast_to_table)
+                .and_then(|x| x.column(name));
+            let semantic = match options.and_then(|x| x.semantic) {
+                Some(_) if !is_string_like => {
+                    return Err(InsError::BuildError(
+                        "clickhouse semantic type",
+                        format!("{} is not a string or binary column", name),
+                    ));
+                }
+                Some(x) => Some(x),
+                // strings cast to a UUID or IP table column are parsed the same way
+                None => table_column
+                    .filter(|_| is_string_like)
+                    .and_then(|x| clickhouse_semantic(&x.ch_type)),
+            };
+            let mut afield = if let Some(x) = semantic {
+                let ch_type = match x {
+                    Semantic::Uuid => "UUID",
+                    Semantic::IPv4 => "IPv4",
+                    Semantic::IPv6 => "IPv6",
+                };
+                self.declared_types
+                    .insert(name.to_owned(), ch_type.to_owned());
+                ArField::new(name, x.arrow_datatype(), !self.spec.not_null.contains(name))
+                    .with_metadata(HashMap::from([(
+                        SEMANTIC_METADATA.to_owned(),
+                        x.name().to_owned(),
+                    )]))
+            } else if let Some(target) = table_column {
+                let adt = clickhouse_to_arrow_datatype(&target.ch_type)?;
+                let is_nullable = target.is_nullable() && !self.spec.not_null.contains(name);
+                ArField::new(name, adt, is_nullable)
+            } else {
+                let adt = clickhouse_arrow_datatype(
+                    &polars_to_arrow_datatype(&pladt)?,
+                    &self.type_options,
+                );
+                let is_nullable =
+                    is_nullable_arrow(!self.spec.not_null.contains(name), &adt, &self.type_options);
+                if let ArDtype::Dictionary(..) = adt {
+                    // the IPC stream identifies each dictionary by its field's id
+                    let dict_id = fields.len() as i64;
+                    ArField::new_dict(name, adt, is_nullable, dict_id, false)
+                } else {
+                    ArField::new(name, adt, is_nullable)
+                }
+            };
+            if let Some(p) = path {
+                afield = afield.with_metadata(HashMap::from([(
+                    SOURCE_PATH_METADATA.to_owned(),
+                    p.to_owned(),
+                )]));
+            }
+            let is_overridden = self.spec.override_fields.contains_key(name);
+            fields.push((name.to_owned(), (!is_overridden).then_some(pladt)));
             schema_builder.push(afield);
         }
+        // columns only declared through with_field keep their relative order after the frame's
+        for (name, dtype) in std::mem::take(&mut self.spec.fields) {
+            if !fields.iter().any(|(x, _)| *x == name) {
+                fields.push((name, dtype));
+            }
+        }
+        self.spec.fields = fields;
         self.schema = Arc::new(schema_builder.finish());
         Ok(self)
     }
 
-    pub fn get_insert_query(&self) -> InsResult<&str> {
-        match self.cached_insert_query.as_deref() {
-            Some(x) => Ok(x),
+    pub fn with_table_description(mut self, description: TableDescription) -> Self {
+        let _ = self.table_description.insert(description);
+        self
+    }
+
+    /// Encode the body with the column types of the table loaded through
+    /// `with_table_description`, casting the frame's values to match.
+    /// Must be set before the schema is derived.
+    pub fn with_cast_to_table(mut self) -> Self {
+        self.cast_to_table = true;
+        self
+    }
+
+    pub fn with_null_policy(mut self, policy: NullPolicy) -> Self {
+        self.null_policy = policy;
+        self
+    }
+
+    pub fn with_date_mapping(mut self, mapping: DateMapping) -> Self {
+        self.type_options.dates = mapping;
+        self
+    }
+
+    pub fn with_range_policy(mut self, policy: RangePolicy) -> Self {
+        self.range_policy = policy;
+        self
+    }
+
+    /// Date and DateTime columns with the ClickHouse type they are inserted
+    /// as: the table's under `with_cast_to_table`, the derived one otherwise.
+    /// Columns given with `with_field` are left to the server.
+    fn temporal_columns(&self) -> InsResult<Vec<(String, String)>> {
+        let mut columns = vec![];
+        for (name, pladt) in self.spec.fields.iter() {
+            if self.spec.override_fields.contains_key(name) {
+                continue;
+            }
+            let table_type = self
+                .table_description
+                .as_ref()
+                .filter(|_| self.cast_to_table)
+                .and_then(|x| x.column(name))
+                .map(|x| x.ch_type.clone());
+            let ch_type = match table_type {
+                Some(x) => Some(x),
+                None => self.column_type(name, pladt.as_ref())?.1,
+            };
+            if let Some(t) = ch_type
+                && clickhouse_temporal_range(&t).is_some()
+            {
+                columns.push((name.clone(), t));
+            }
+        }
+        Ok(columns)
+    }
+
+    fn frame_checks(&self) -> InsResult<FrameChecks> {
+        Ok(FrameChecks {
+            schema: self.schema.clone(),
+            null_policy: self.null_policy,
+            range_policy: self.range_policy,
+            temporal: self.temporal_columns()?,
+        })
+    }
+
+    /// Values of `frame` outside the range of their Date or DateTime column
+    /// type, including strings and integers cast to one.
+    pub fn check_ranges(
+        &self,
+        frame: &polars::prelude::DataFrame,
+    ) -> InsResult<Vec<RangeViolation>> {
+        range_violations(&self.temporal_columns()?, frame)
+    }
+
+    /// NOT NULL columns of the built schema holding nulls in `frame`.
+    pub fn check_not_null(&self, frame: &polars::prelude::DataFrame) -> Vec<NullViolation> {
+        null_violations(&self.schema, frame)
+    }
+
+    /// Compares the frame against the table loaded with `with_table_description`.
+    pub fn check_table_schema(
+        &self,
+        frame: &polars::prelude::DataFrame,
+    ) -> InsResult<SchemaReport> {
+        let table = match self.table_description.as_ref() {
+            Some(x) => x,
+            None => {
+                return Err(InsError::BuildError(
+                    "clickhouse schema check",
+                    "no table description, first run self.with_table_description".to_owned(),
+                ));
+            }
+        };
+        let mut report = SchemaReport::default();
+        for column in table.columns.iter() {
+            if column.is_insertable() && frame.column(&column.name).is_err() {
+                report.missing_columns.push(column.name.clone());
+            }
+        }
+        for (name, pladt) in self.spec.fields.iter() {
+            let Ok(values) = frame.column(name) else {
+                continue;
+            };
+            let target = match table.column(name) {
+                Some(x) if x.is_insertable() => x,
+                _ => {
+                    report.extra_columns.push(name.clone());
+                    continue;
+                }
+            };
+            if !target.is_nullable() && values.null_count() > 0 && !values.dtype().is_nested() {
+                report.nullability_conflicts.push(NullabilityConflict {
+                    column: name.clone(),
+                    null_count: values.null_count(),
+                });
+            }
+            if let (_, Some(frame_type)) = self.column_type(name, pladt.as_ref())?
+                && !clickhouse_types_match(&frame_type, &target.ch_type)
+            {
+                report.type_mismatches.push(TypeMismatch {
+                    column: name.clone(),
+                    frame_type,
+                    table_type: target.ch_type.clone(),
+                });
+            }
+        }
+        Ok(report)
+    }
+
+    /// Statements bringing the table loaded with `with_table_description` in
+    /// line with the frame, empty if no description was given.
+    pub fn get_alter_queries(&self) -> InsResult<&[String]> {
+        match self.cached_create_query {
+            Some(_) => Ok(self.cached_alter_queries.as_slice()),
             None => Err(InsError::BuildError(
-                "clickhouse insert_query",
+                "clickhouse alter_queries",
                 "not yet built, first run self.build_queries".to_owned(),
             )),
         }
@@ -264,6 +1710,70 @@ impl ClickhouseInserter {
 
     pub fn get_arrow_body(&self, frame: &polars::prelude::DataFrame) -> InsResult<Vec<u8>> {
         let schema = self.schema.clone();
-        arrow_to_bytes(schema, frame)
+        let frame = self.frame_checks()?.apply(frame.clone())?;
+        arrow_to_bytes(schema, &frame, self.max_batch_rows)
+    }
+
+    pub fn write_arrow_body<W: std::io::Write>(
+        &self,
+        frame: &polars::prelude::DataFrame,
+        writer: W,
+    ) -> InsResult<W> {
+        let schema = self.schema.clone();
+        let frame = self.frame_checks()?.apply(frame.clone())?;
+        arrow_to_writer(schema, &frame, self.max_batch_rows, writer)
+    }
+
+    pub fn get_arrow_chunks(
+        &self,
+        frame: &polars::prelude::DataFrame,
+    ) -> InsResult<ArrowStreamChunks> {
+        let schema = self.schema.clone();
+        let frame = self.frame_checks()?.apply(frame.clone())?;
+        ArrowStreamChunks::new(schema, frame, self.max_batch_rows)
+    }
+
+    pub fn get_arrow_chunks_from_frames(
+        &self,
+        frames: impl Iterator<Item = polars::prelude::DataFrame> + Send + 'static,
+    ) -> InsResult<ArrowStreamChunks> {
+        let checks = self.frame_checks()?;
+        let schema = checks.schema.clone();
+        let frames = frames.map(move |x| checks.apply(x));
+        ArrowStreamChunks::from_frames(schema, frames, self.max_batch_rows)
+    }
+
+    /// Collects `frame` and encodes the result in frames of `batch_size`
+    /// rows. The whole result stays in memory until the stream ends, so data
+    /// larger than memory has to be read in bounded frames and passed to
+    /// `get_arrow_chunks_from_frames` instead.
+    #[cfg(feature = "lazy")]
+    pub fn get_arrow_chunks_from_lazy(
+        &self,
+        frame: polars::prelude::LazyFrame,
+        batch_size: usize,
+    ) -> InsResult<ArrowStreamChunks> {
+        if batch_size == 0 {
+            return Err(InsError::BuildError(
+                "lazy batches",
+                "batch_size must be positive".to_owned(),
+            ));
+        }
+        let collected = match frame.collect() {
+            Ok(x) => x,
+            Err(e) => {
+                return Err(InsError::ConvertError(
+                    "failed collecting lazy frame",
+                    e.to_string(),
+                ));
+            }
+        };
+        let frames = (0..collected.height())
+            .step_by(batch_size)
+            .map(move |offset| Ok(collected.slice(offset as i64, batch_size)));
+        let checks = self.frame_checks()?;
+        let schema = checks.schema.clone();
+        let frames = frames.map(move |x| x.and_then(|f| checks.apply(f)));
+        ArrowStreamChunks::from_frames(schema, frames, self.max_batch_rows)
     }
 }
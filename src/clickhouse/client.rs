@@ -0,0 +1,210 @@
+use reqwest::blocking::{Body, Client, Response};
+
+use crate::{
+    clickhouse::{ClickhouseInserter, describe::TableDescription, exception::parse_exception},
+    common::{ArrowStreamChunks, Executor, InsError, InsResult, Inserter},
+};
+
+pub struct ClickhouseClient {
+    pub url: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    client: Client,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct QuerySummary {
+    pub query_id: Option<String>,
+    pub read_rows: u64,
+    pub read_bytes: u64,
+    pub written_rows: u64,
+    pub written_bytes: u64,
+}
+
+// X-ClickHouse-Summary is a flat JSON object of stringified counters,
+// e.g. {"read_rows":"0","written_rows":"4",...}
+fn summary_counter(summary: &str, key: &str) -> u64 {
+    let pattern = format!("\"{}\":\"", key);
+    summary
+        .find(pattern.as_str())
+        .map(|idx| &summary[idx + pattern.len()..])
+        .and_then(|rest| rest.split('"').next())
+        .and_then(|x| x.parse().ok())
+        .unwrap_or_default()
+}
+
+impl QuerySummary {
+    fn from_response(response: &Response) -> Self {
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|x| x.to_str().ok())
+                .map(|x| x.to_owned())
+        };
+        let summary = header("X-ClickHouse-Summary").unwrap_or_default();
+        Self {
+            query_id: header("X-ClickHouse-Query-Id"),
+            read_rows: summary_counter(&summary, "read_rows"),
+            read_bytes: summary_counter(&summary, "read_bytes"),
+            written_rows: summary_counter(&summary, "written_rows"),
+            written_bytes: summary_counter(&summary, "written_bytes"),
+        }
+    }
+}
+
+impl Executor for ClickhouseClient {
+    type Summary = QuerySummary;
+
+    fn execute_query<R: std::io::Read + Send + 'static>(
+        &self,
+        query: &str,
+        body: Option<R>,
+    ) -> InsResult<QuerySummary> {
+        self.execute(query, body.map(Body::new))
+    }
+}
+
+impl ClickhouseClient {
+    pub fn default(url: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            user: None,
+            password: None,
+            client: Client::new(),
+        }
+    }
+
+    pub fn with_credentials(mut self, user: &str, password: &str) -> Self {
+        let _ = self.user.insert(user.to_owned());
+        let _ = self.password.insert(password.to_owned());
+        self
+    }
+
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    fn send(&self, query: &str, body: Option<Body>) -> InsResult<Response> {
+        let mut req = self
+            .client
+            .post(self.url.as_str())
+            .query(&[("query", query), ("wait_end_of_query", "1")]);
+        if let Some(user) = self.user.as_deref() {
+            req = req.basic_auth(user, self.password.as_deref());
+        }
+        req = match body {
+            Some(b) => req.body(b),
+            None => req.header("Content-Length", 0),
+        };
+        let response = match req.send() {
+            Ok(x) => x,
+            // only failures to reach the server are worth retrying
+            Err(e) if e.is_connect() || e.is_timeout() => {
+                return Err(InsError::ConnectionError("clickhouse http", e.to_string()));
+            }
+            Err(e) => {
+                return Err(InsError::RequestError("clickhouse http", e.to_string()));
+            }
+        };
+        let status = response.status();
+        if status.is_success() {
+            Ok(response)
+        } else {
+            let text = response.text().unwrap_or_default();
+            Err(parse_exception(&text, query)
+                .unwrap_or(InsError::ResponseError(status.as_u16(), text)))
+        }
+    }
+
+    pub fn execute(&self, query: &str, body: Option<Body>) -> InsResult<QuerySummary> {
+        let response = self.send(query, body)?;
+        Ok(QuerySummary::from_response(&response))
+    }
+
+    /// Sends `chunks` as the body of `query`. An error raised while encoding
+    /// is returned in place of the transport error that aborted the request.
+    pub fn execute_stream(
+        &self,
+        query: &str,
+        chunks: ArrowStreamChunks,
+    ) -> InsResult<QuerySummary> {
+        let failure = chunks.failure();
+        let result = self.execute(query, Some(Body::new(chunks)));
+        match failure.lock().ok().and_then(|mut x| x.take()) {
+            Some(e) => Err(e),
+            None => result,
+        }
+    }
+
+    pub fn query_text(&self, query: &str) -> InsResult<String> {
+        match self.send(query, None)?.text() {
+            Ok(x) => Ok(x),
+            Err(e) => Err(InsError::RequestError(
+                "clickhouse response body",
+                e.to_string(),
+            )),
+        }
+    }
+
+    pub fn describe_table(&self, ins: &ClickhouseInserter) -> InsResult<TableDescription> {
+        let query = format!(
+            "DESCRIBE TABLE {} FORMAT TabSeparated",
+            ins.get_table_name()
+        );
+        TableDescription::from_tab_separated(&self.query_text(&query)?)
+    }
+
+    pub fn create_table(&self, ins: &ClickhouseInserter) -> InsResult<QuerySummary> {
+        self.execute(ins.get_create_query()?, None)
+    }
+
+    pub fn migrate_table(&self, ins: &ClickhouseInserter) -> InsResult<Vec<QuerySummary>> {
+        let mut summaries = vec![];
+        for query in ins.get_alter_queries()? {
+            summaries.push(self.execute(query, None)?);
+        }
+        Ok(summaries)
+    }
+
+    pub fn insert(
+        &self,
+        ins: &ClickhouseInserter,
+        frame: &polars::prelude::DataFrame,
+    ) -> InsResult<QuerySummary> {
+        let body = ins.get_arrow_chunks(frame)?;
+        self.execute_stream(ins.get_insert_query()?, body)
+    }
+
+    /// Every frame is checked and cast before its first byte is sent, but
+    /// the server may already have committed the frames sent before a failing one.
+    pub fn insert_frames(
+        &self,
+        ins: &ClickhouseInserter,
+        frames: impl Iterator<Item = polars::prelude::DataFrame> + Send + 'static,
+    ) -> InsResult<QuerySummary> {
+        let body = ins.get_arrow_chunks_from_frames(frames)?;
+        self.execute_stream(ins.get_insert_query()?, body)
+    }
+
+    #[cfg(feature = "lazy")]
+    pub fn insert_lazy(
+        &self,
+        ins: &ClickhouseInserter,
+        frame: polars::prelude::LazyFrame,
+        batch_size: usize,
+    ) -> InsResult<QuerySummary> {
+        let body = ins.get_arrow_chunks_from_lazy(frame, batch_size)?;
+        self.execute_stream(ins.get_insert_query()?, body)
+    }
+
+    pub fn create_and_insert(
+        &self,
+        ins: &ClickhouseInserter,
+        frame: &polars::prelude::DataFrame,
+    ) -> InsResult<QuerySummary> {
+        self.create_table(ins)?;
+        self.insert(ins, frame)
+    }
+}
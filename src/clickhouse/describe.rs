@@ -0,0 +1,359 @@
+use std::sync::Arc;
+
+use crate::common::{ArDtype, ArField, ArFields, InsError, InsResult, Semantic};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnDescription {
+    pub name: String,
+    pub ch_type: String,
+    pub default_kind: Option<String>,
+    pub default_expression: Option<String>,
+    pub comment: Option<String>,
+    pub codec: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableDescription {
+    pub columns: Vec<ColumnDescription>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NullabilityConflict {
+    pub column: String,
+    pub null_count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeMismatch {
+    pub column: String,
+    pub frame_type: String,
+    pub table_type: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchemaReport {
+    /// Table columns absent from the frame; the server fills them with defaults.
+    pub missing_columns: Vec<String>,
+    /// Frame columns the table cannot accept.
+    pub extra_columns: Vec<String>,
+    pub nullability_conflicts: Vec<NullabilityConflict>,
+    pub type_mismatches: Vec<TypeMismatch>,
+}
+
+impl SchemaReport {
+    pub fn is_compatible(&self) -> bool {
+        self.extra_columns.is_empty()
+            && self.nullability_conflicts.is_empty()
+            && self.type_mismatches.is_empty()
+    }
+}
+
+fn unescape_tsv(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some('b') => out.push('\u{8}'),
+            Some('f') => out.push('\u{c}'),
+            Some(x) => out.push(x),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Strips `Nullable(...)` and `LowCardinality(...)` wrappers, leaving the
+/// type the values are stored as.
+pub fn clickhouse_base_type(ch_type: &str) -> &str {
+    let mut t = ch_type.trim();
+    loop {
+        let inner = ["Nullable(", "LowCardinality("]
+            .iter()
+            .find_map(|w| t.strip_prefix(w).and_then(|x| x.strip_suffix(')')));
+        match inner {
+            Some(x) => t = x.trim(),
+            None => return t,
+        }
+    }
+}
+
+pub fn clickhouse_is_nullable(ch_type: &str) -> bool {
+    let t = ch_type.trim();
+    let t = t
+        .strip_prefix("LowCardinality(")
+        .and_then(|x| x.strip_suffix(')'))
+        .unwrap_or(t);
+    t.starts_with("Nullable(")
+}
+
+/// Drops whitespace and the backtick quoting of tuple member names, which
+/// `DESCRIBE` omits, leaving string literals such as enum values untouched.
+fn normalized_type(ch_type: &str) -> String {
+    let mut out = String::with_capacity(ch_type.len());
+    let (mut quoted, mut backticked, mut escaped) = (false, false, false);
+    for c in clickhouse_base_type(ch_type).chars() {
+        match c {
+            _ if escaped => {
+                out.push(c);
+                escaped = false;
+            }
+            '\\' if quoted || backticked => {
+                escaped = true;
+                if quoted {
+                    out.push(c);
+                }
+            }
+            '\'' if !backticked => {
+                quoted = !quoted;
+                out.push(c);
+            }
+            '`' if !quoted => backticked = !backticked,
+            _ if c.is_whitespace() && !quoted && !backticked => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn clickhouse_types_match(left: &str, right: &str) -> bool {
+    normalized_type(left) == normalized_type(right)
+}
+
+/// Splits `Name(args)` into the name and its comma separated top-level
+/// arguments, ignoring commas inside nested parentheses and quotes.
+fn type_call(ch_type: &str) -> (&str, Vec<&str>) {
+    let t = ch_type.trim();
+    let Some(open) = t.find('(') else {
+        return (t, vec![]);
+    };
+    let inner = t[open + 1..].strip_suffix(')').unwrap_or(&t[open + 1..]);
+    let mut args = vec![];
+    let (mut depth, mut quoted, mut escaped, mut start) = (0, false, false, 0);
+    for (idx, c) in inner.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '\'' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                args.push(inner[start..idx].trim());
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(inner[start..].trim());
+    (t[..open].trim(), args)
+}
+
+fn unquote(value: &str) -> String {
+    let v = value.trim();
+    v.strip_prefix('\'')
+        .and_then(|x| x.strip_suffix('\''))
+        .unwrap_or(v)
+        .replace("\\'", "'")
+        .replace("\\\\", "\\")
+}
+
+fn parse_number<T: std::str::FromStr>(ch_type: &str, arg: Option<&&str>) -> InsResult<T> {
+    match arg.and_then(|x| x.parse().ok()) {
+        Some(x) => Ok(x),
+        None => Err(InsError::ConvertError(
+            "clickhouse type",
+            format!("bad numeric argument in {}", ch_type),
+        )),
+    }
+}
+
+fn clickhouse_decimal(precision: u8, scale: i8) -> ArDtype {
+    if precision <= 38 {
+        ArDtype::Decimal128(precision, scale)
+    } else {
+        ArDtype::Decimal256(precision, scale)
+    }
+}
+
+/// Arrow type sent in the body for a ClickHouse column type; strings travel
+/// as binary like the rest of the crate. Nullability is read separately with
+/// `clickhouse_is_nullable`.
+pub fn clickhouse_to_arrow_datatype(ch_type: &str) -> InsResult<ArDtype> {
+    let base = clickhouse_base_type(ch_type);
+    let (name, args) = type_call(base);
+    let time_unit = |precision: u8| match precision {
+        0 => arrow::datatypes::TimeUnit::Second,
+        1..=3 => arrow::datatypes::TimeUnit::Millisecond,
+        4..=6 => arrow::datatypes::TimeUnit::Microsecond,
+        _ => arrow::datatypes::TimeUnit::Nanosecond,
+    };
+    Ok(match name {
+        "Bool" => ArDtype::Boolean,
+        "Int8" => ArDtype::Int8,
+        "Int16" => ArDtype::Int16,
+        "Int32" => ArDtype::Int32,
+        "Int64" => ArDtype::Int64,
+        "UInt8" => ArDtype::UInt8,
+        "UInt16" => ArDtype::UInt16,
+        "UInt32" => ArDtype::UInt32,
+        "UInt64" => ArDtype::UInt64,
+        "Float32" => ArDtype::Float32,
+        "Float64" => ArDtype::Float64,
+        "String" | "Enum8" | "Enum16" => ArDtype::Binary,
+        "UUID" | "IPv6" => ArDtype::FixedSizeBinary(16),
+        "IPv4" => ArDtype::UInt32,
+        "FixedString" => ArDtype::FixedSizeBinary(parse_number(base, args.first())?),
+        "Date" | "Date32" => ArDtype::Date32,
+        "DateTime" => ArDtype::Timestamp(
+            arrow::datatypes::TimeUnit::Second,
+            args.first().map(|x| Arc::from(unquote(x))),
+        ),
+        "DateTime64" => ArDtype::Timestamp(
+            time_unit(parse_number(base, args.first())?),
+            args.get(1).map(|x| Arc::from(unquote(x))),
+        ),
+        "Decimal" => clickhouse_decimal(
+            parse_number(base, args.first())?,
+            parse_number(base, args.get(1))?,
+        ),
+        "Decimal32" => clickhouse_decimal(9, parse_number(base, args.first())?),
+        "Decimal64" => clickhouse_decimal(18, parse_number(base, args.first())?),
+        "Decimal128" => clickhouse_decimal(38, parse_number(base, args.first())?),
+        "Decimal256" => clickhouse_decimal(76, parse_number(base, args.first())?),
+        "Array" => {
+            let inner = args.first().copied().unwrap_or_default();
+            ArDtype::LargeList(Arc::new(ArField::new(
+                "item",
+                clickhouse_to_arrow_datatype(inner)?,
+                clickhouse_is_nullable(inner),
+            )))
+        }
+        "Tuple" => {
+            let mut fields = vec![];
+            for (idx, arg) in args.iter().enumerate() {
+                // named tuples declare `name Type`, positional ones only `Type`
+                let (field_name, inner) = match arg.split_once(' ') {
+                    Some((n, t)) if !n.contains('(') => (unquote(n.trim_matches('`')), t),
+                    _ => ((idx + 1).to_string(), *arg),
+                };
+                fields.push(ArField::new(
+                    field_name,
+                    clickhouse_to_arrow_datatype(inner)?,
+                    clickhouse_is_nullable(inner),
+                ));
+            }
+            ArDtype::Struct(ArFields::from_iter(fields))
+        }
+        "Map" if args.len() == 2 => {
+            let entries = ArFields::from_iter([
+                ArField::new("key", clickhouse_to_arrow_datatype(args[0])?, false),
+                ArField::new(
+                    "value",
+                    clickhouse_to_arrow_datatype(args[1])?,
+                    clickhouse_is_nullable(args[1]),
+                ),
+            ]);
+            ArDtype::Map(
+                Arc::new(ArField::new("entries", ArDtype::Struct(entries), false)),
+                false,
+            )
+        }
+        _ => {
+            return Err(InsError::ConversionNotImplementedError(format!(
+                "clickhouse_to_arrow: {}",
+                ch_type
+            )));
+        }
+    })
+}
+
+pub fn clickhouse_semantic(ch_type: &str) -> Option<Semantic> {
+    match clickhouse_base_type(ch_type) {
+        "UUID" => Some(Semantic::Uuid),
+        "IPv4" => Some(Semantic::IPv4),
+        "IPv6" => Some(Semantic::IPv6),
+        _ => None,
+    }
+}
+
+/// Seconds since the epoch a `Date`, `Date32`, `DateTime` or `DateTime64`
+/// column can hold, both ends included.
+pub fn clickhouse_temporal_range(ch_type: &str) -> Option<(i64, i64)> {
+    const DAY: i64 = 86_400;
+    // Date32 and DateTime64 span 1900-01-01 to 2299-12-31
+    let wide = (-25_567 * DAY, 120_530 * DAY - 1);
+    match type_call(clickhouse_base_type(ch_type)).0 {
+        "Date" => Some((0, 65_536 * DAY - 1)),
+        "DateTime" => Some((0, u32::MAX as i64)),
+        "Date32" | "DateTime64" => Some(wide),
+        _ => None,
+    }
+}
+
+impl ColumnDescription {
+    pub fn is_nullable(&self) -> bool {
+        clickhouse_is_nullable(&self.ch_type)
+    }
+
+    /// MATERIALIZED and ALIAS columns are computed by the server and cannot
+    /// be inserted into.
+    pub fn is_insertable(&self) -> bool {
+        !matches!(
+            self.default_kind.as_deref(),
+            Some("MATERIALIZED") | Some("ALIAS")
+        )
+    }
+}
+
+impl TableDescription {
+    /// Parses the TabSeparated output of `DESCRIBE TABLE`, or of a
+    /// `system.columns` query selecting `name, type, default_kind,
+    /// default_expression, comment, compression_codec` in that order.
+    /// A `TabSeparatedWithNames` header row is skipped.
+    pub fn from_tab_separated(body: &str) -> InsResult<Self> {
+        let mut columns = vec![];
+        for (idx, line) in body.lines().enumerate() {
+            if line.is_empty() || (idx == 0 && line.starts_with("name\ttype")) {
+                continue;
+            }
+            let values = line.split('\t').map(unescape_tsv).collect::<Vec<_>>();
+            if values.len() < 2 {
+                return Err(InsError::ConvertError(
+                    "clickhouse table description",
+                    format!(
+                        "expected at least name and type on line {}: {}",
+                        idx + 1,
+                        line
+                    ),
+                ));
+            }
+            let optional = |i: usize| values.get(i).filter(|x| !x.is_empty()).cloned();
+            columns.push(ColumnDescription {
+                name: values[0].clone(),
+                ch_type: values[1].clone(),
+                default_kind: optional(2),
+                default_expression: optional(3),
+                comment: optional(4),
+                codec: optional(5),
+            });
+        }
+        if columns.is_empty() {
+            return Err(InsError::ConvertError(
+                "clickhouse table description",
+                "no columns described".to_owned(),
+            ));
+        }
+        Ok(Self { columns })
+    }
+
+    pub fn column(&self, name: &str) -> Option<&ColumnDescription> {
+        self.columns.iter().find(|c| c.name == name)
+    }
+}
@@ -0,0 +1,114 @@
+use crate::common::InsError;
+
+// Servers older than 21.x only print the numeric code, so the names needed
+// for classification are resolved from this table when missing.
+const KNOWN_CODES: &[(u32, &str)] = &[
+    (6, "CANNOT_PARSE_TEXT"),
+    (7, "INCORRECT_NUMBER_OF_COLUMNS"),
+    (8, "THERE_IS_NO_COLUMN"),
+    (10, "NOT_FOUND_COLUMN_IN_BLOCK"),
+    (16, "NO_SUCH_COLUMN_IN_TABLE"),
+    (20, "NUMBER_OF_COLUMNS_DOESNT_MATCH"),
+    (47, "UNKNOWN_IDENTIFIER"),
+    (53, "TYPE_MISMATCH"),
+    (60, "UNKNOWN_TABLE"),
+    (70, "CANNOT_CONVERT_TYPE"),
+    (159, "TIMEOUT_EXCEEDED"),
+    (202, "TOO_MANY_SIMULTANEOUS_QUERIES"),
+    (209, "SOCKET_TIMEOUT"),
+    (210, "NETWORK_ERROR"),
+    (241, "MEMORY_LIMIT_EXCEEDED"),
+    (242, "TABLE_IS_READ_ONLY"),
+    (252, "TOO_MANY_PARTS"),
+    (285, "TOO_FEW_LIVE_REPLICAS"),
+    (319, "UNKNOWN_STATUS_OF_INSERT"),
+    (349, "CANNOT_INSERT_NULL_IN_ORDINARY_COLUMN"),
+    (999, "KEEPER_EXCEPTION"),
+];
+
+const RETRYABLE: &[&str] = &[
+    "TIMEOUT_EXCEEDED",
+    "TOO_MANY_SIMULTANEOUS_QUERIES",
+    "SOCKET_TIMEOUT",
+    "NETWORK_ERROR",
+    "MEMORY_LIMIT_EXCEEDED",
+    "TABLE_IS_READ_ONLY",
+    "TOO_MANY_PARTS",
+    "TOO_FEW_LIVE_REPLICAS",
+    "UNKNOWN_STATUS_OF_INSERT",
+    "KEEPER_EXCEPTION",
+];
+
+const SCHEMA_MISMATCH: &[&str] = &[
+    "CANNOT_PARSE_TEXT",
+    "INCORRECT_NUMBER_OF_COLUMNS",
+    "THERE_IS_NO_COLUMN",
+    "NOT_FOUND_COLUMN_IN_BLOCK",
+    "NO_SUCH_COLUMN_IN_TABLE",
+    "NUMBER_OF_COLUMNS_DOESNT_MATCH",
+    "UNKNOWN_IDENTIFIER",
+    "TYPE_MISMATCH",
+    "CANNOT_CONVERT_TYPE",
+    "CANNOT_INSERT_NULL_IN_ORDINARY_COLUMN",
+];
+
+fn code_name(code: u32) -> Option<&'static str> {
+    KNOWN_CODES
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map(|(_, name)| *name)
+}
+
+/// Parses a `Code: NNN. DB::Exception: ...` response body into
+/// [`InsError::ServerError`], returning `None` if the body is not an exception.
+pub fn parse_exception(body: &str, query: &str) -> Option<InsError> {
+    let rest = body.trim().strip_prefix("Code: ")?;
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let code = rest[..digits].parse::<u32>().ok()?;
+    let mut message = rest
+        .split_once("DB::Exception: ")
+        .map(|(_, m)| m)
+        .unwrap_or(&rest[digits..])
+        .trim();
+    if let Some(idx) = message.rfind(" (version ") {
+        message = message[..idx].trim_end();
+    }
+    let mut name = None;
+    if let Some(inner) = message.strip_suffix(')')
+        && let Some((msg, n)) = inner.rsplit_once('(')
+        && !n.is_empty()
+        && n.chars().all(|c| c.is_ascii_uppercase() || c == '_')
+    {
+        name = Some(n.to_owned());
+        message = msg.trim_end();
+    }
+    let name = name
+        .or_else(|| code_name(code).map(|x| x.to_owned()))
+        .unwrap_or_default();
+    Some(InsError::ServerError {
+        code,
+        name,
+        message: message.trim_end_matches('.').to_owned(),
+        query: query.to_owned(),
+    })
+}
+
+impl InsError {
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            InsError::ServerError { name, .. } => RETRYABLE.contains(&name.as_str()),
+            InsError::ConnectionError(..) => true,
+            InsError::ResponseError(status, _) => matches!(status, 502..=504),
+            _ => false,
+        }
+    }
+
+    pub fn is_schema_mismatch(&self) -> bool {
+        match self {
+            InsError::ServerError { name, .. } => SCHEMA_MISMATCH.contains(&name.as_str()),
+            _ => false,
+        }
+    }
+}
@@ -1,15 +1,23 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Write},
+    sync::Arc,
+};
 
 use arrow::{array::AsArray, ffi::from_ffi};
 use arrow_array::Array;
 use arrow_ipc::writer::StreamWriter;
 use polars::prelude::CompatLevel;
-use polars_arrow::ffi::{export_array_to_c, export_field_to_c};
+use polars_arrow::{
+    datatypes::IntegerType,
+    ffi::{export_array_to_c, export_field_to_c},
+};
 use thiserror::Error;
 
 pub type PlColumn = polars::prelude::Column;
 pub type PlDtype = polars::prelude::DataType;
 pub type PlArrowDtype = polars::prelude::ArrowDataType;
+pub type PlArrowField = polars::prelude::ArrowField;
 pub type ArDtype = arrow::datatypes::DataType;
 pub type ArField = arrow::datatypes::Field;
 pub type ArFields = arrow::datatypes::Fields;
@@ -22,11 +30,107 @@ pub enum InsError {
     ConvertError(&'static str, String),
     #[error("No conversion of {0}")]
     ConversionNotImplementedError(String),
+    #[error("Failed to cast column {0}: {1}")]
+    CastError(String, String),
+    #[error("Nulls in NOT NULL columns: {0}")]
+    NullError(String),
+    #[error("Values out of the column type range: {0}")]
+    RangeError(String),
+    #[error("Request failed ({0}): {1}")]
+    RequestError(&'static str, String),
+    #[error("Connection failed ({0}): {1}")]
+    ConnectionError(&'static str, String),
+    #[error("Server responded with status {0}: {1}")]
+    ResponseError(u16, String),
+    #[error("Server error {code} ({name}): {message}\nQuery: {query}")]
+    ServerError {
+        code: u32,
+        name: String,
+        message: String,
+        query: String,
+    },
 }
 
 pub type InsResult<T> = Result<T, InsError>;
 
+/// Field metadata key marking a string or binary column to be encoded as a
+/// [`Semantic`] type, holding [`Semantic::name`].
+pub const SEMANTIC_METADATA: &str = "inserter_x.semantic";
+
+/// Field metadata key marking a field flattened out of a struct column,
+/// holding the column name and the nested field names joined by
+/// [`SOURCE_PATH_SEPARATOR`].
+pub const SOURCE_PATH_METADATA: &str = "inserter_x.source_path";
+pub const SOURCE_PATH_SEPARATOR: char = '\u{1f}';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Semantic {
+    Uuid,
+    IPv4,
+    IPv6,
+}
+
+impl Semantic {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Semantic::Uuid => "uuid",
+            Semantic::IPv4 => "ipv4",
+            Semantic::IPv6 => "ipv6",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Semantic::Uuid, Semantic::IPv4, Semantic::IPv6]
+            .into_iter()
+            .find(|x| x.name() == name)
+    }
+
+    /// Arrow type the values are sent as: UUIDs as 16 bytes in RFC 4122
+    /// order, IPv4 as its 32-bit number and IPv6 as 16 bytes in network order.
+    pub fn arrow_datatype(&self) -> ArDtype {
+        match self {
+            Semantic::Uuid | Semantic::IPv6 => ArDtype::FixedSizeBinary(16),
+            Semantic::IPv4 => ArDtype::UInt32,
+        }
+    }
+
+    fn parse(&self, text: &str) -> Option<[u8; 16]> {
+        match self {
+            Semantic::Uuid => {
+                let bytes = text.as_bytes();
+                let hex = match bytes.len() {
+                    36 if [8, 13, 18, 23].iter().all(|&i| bytes[i] == b'-') => {
+                        text.replace('-', "")
+                    }
+                    32 => text.to_owned(),
+                    _ => return None,
+                };
+                if hex.len() != 32 || !hex.bytes().all(|x| x.is_ascii_hexdigit()) {
+                    return None;
+                }
+                let mut out = [0u8; 16];
+                for (idx, byte) in out.iter_mut().enumerate() {
+                    *byte = u8::from_str_radix(&hex[2 * idx..2 * idx + 2], 16).ok()?;
+                }
+                Some(out)
+            }
+            Semantic::IPv4 => {
+                let addr = text.parse::<std::net::Ipv4Addr>().ok()?;
+                Some(addr.to_ipv6_mapped().octets())
+            }
+            // IPv4 addresses are stored as IPv4-mapped IPv6, as ClickHouse does
+            Semantic::IPv6 => match text.parse::<std::net::IpAddr>().ok()? {
+                std::net::IpAddr::V4(x) => Some(x.to_ipv6_mapped().octets()),
+                std::net::IpAddr::V6(x) => Some(x.octets()),
+            },
+        }
+    }
+}
+
 pub trait CreateCmd {
+    fn identifier(name: &str) -> String {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
     fn field(
         name: &str,
         is_nullable: bool,
@@ -36,12 +140,16 @@ pub trait CreateCmd {
         if let Some(d) = dtype {
             format!(
                 "{} {} {} NULL",
-                name,
+                Self::identifier(name),
                 d,
                 (if is_nullable { "" } else { "NOT" }).to_owned(),
             )
         } else {
-            format!("{} {}", name, constraint.unwrap_or_default())
+            format!(
+                "{} {}",
+                Self::identifier(name),
+                constraint.unwrap_or_default()
+            )
         }
     }
     fn table(
@@ -63,6 +171,172 @@ pub trait CreateCmd {
     }
 }
 
+/// Runs queries against a database, streaming `body` as the data of inserts.
+pub trait Executor {
+    type Summary;
+
+    fn execute_query<R: Read + Send + 'static>(
+        &self,
+        query: &str,
+        body: Option<R>,
+    ) -> InsResult<Self::Summary>;
+}
+
+/// Builder state shared by every backend: the table, its columns and the
+/// keys and constraints declared on them.
+#[derive(Debug, Clone, Default)]
+pub struct TableSpec {
+    pub override_creation: Option<String>,
+    pub table_name: String,
+    pub db_name: Option<String>,
+    pub fields: Vec<(String, Option<PlArrowDtype>)>,
+    pub not_null: HashSet<String>,
+    pub override_fields: HashMap<String, String>,
+    pub order_by: Vec<String>,
+    pub primary_key: Vec<String>,
+}
+
+impl TableSpec {
+    pub fn new(table: &str) -> Self {
+        Self {
+            table_name: table.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Declares `column` with `dtype`, keeping its position if already declared.
+    pub fn set_field(&mut self, column: &str, dtype: Option<PlArrowDtype>) {
+        match self.fields.iter_mut().find(|(name, _)| name == column) {
+            Some((_, x)) => *x = dtype,
+            None => self.fields.push((column.to_owned(), dtype)),
+        }
+    }
+}
+
+/// A database backend: maps frame columns to its types, builds the DDL and
+/// insert statements and encodes frames into insert bodies. The builder
+/// methods for the [`TableSpec`] come with the trait, backends only add the
+/// options specific to them.
+pub trait Inserter: CreateCmd + Sized {
+    fn spec(&self) -> &TableSpec;
+
+    fn spec_mut(&mut self) -> &mut TableSpec;
+
+    /// SQL type of a column of arrow type `dtype`.
+    fn sql_type(&self, dtype: &PlArrowDtype) -> InsResult<String>;
+
+    fn with_schema_from_cols(self, columns: &[PlColumn]) -> InsResult<Self>;
+
+    /// Generates the statements returned by the `get_*_query` methods.
+    fn build_queries(self) -> InsResult<Self>;
+
+    fn get_create_query(&self) -> InsResult<&str>;
+
+    fn get_insert_query(&self) -> InsResult<&str>;
+
+    /// Encodes `frame` into the body streamed with the insert query.
+    fn get_body(&self, frame: &polars::prelude::DataFrame)
+    -> InsResult<impl Read + Send + 'static>;
+
+    fn with_table_name(mut self, table_name: &str) -> Self {
+        self.spec_mut().table_name = table_name.to_owned();
+        self
+    }
+
+    fn with_dbname(mut self, db_name: &str) -> Self {
+        let _ = self.spec_mut().db_name.insert(db_name.to_owned());
+        self
+    }
+
+    fn with_create_method(mut self, override_creation: &str) -> Self {
+        let _ = self
+            .spec_mut()
+            .override_creation
+            .insert(override_creation.to_owned());
+        self
+    }
+
+    fn with_field(mut self, column: &str, constraint: &str) -> Self {
+        let spec = self.spec_mut();
+        spec.set_field(column, None);
+        spec.override_fields
+            .insert(column.to_owned(), constraint.to_owned());
+        self
+    }
+
+    fn with_order_by(mut self, subkeys: Vec<String>) -> Self {
+        let spec = self.spec_mut();
+        spec.order_by.extend_from_slice(subkeys.as_slice());
+        spec.not_null.extend(subkeys);
+        self
+    }
+
+    /// Replaces the ORDER BY keys. The old keys stay NOT NULL, as they may
+    /// have been declared so on their own; `replace_not_null` resets them.
+    fn replace_order_by(mut self, subkeys: Vec<String>) -> Self {
+        let spec = self.spec_mut();
+        spec.order_by.clear();
+        spec.order_by.extend_from_slice(subkeys.as_slice());
+        spec.not_null.extend(subkeys);
+        self
+    }
+
+    fn with_not_null(mut self, subkeys: Vec<String>) -> Self {
+        self.spec_mut().not_null.extend(subkeys);
+        self
+    }
+
+    fn replace_not_null(mut self, subkeys: Vec<String>) -> Self {
+        let spec = self.spec_mut();
+        spec.not_null.clear();
+        spec.not_null.extend(subkeys);
+        self
+    }
+
+    fn with_primary_key(mut self, subkeys: Vec<String>) -> Self {
+        let spec = self.spec_mut();
+        spec.primary_key.extend_from_slice(subkeys.as_slice());
+        spec.not_null.extend(subkeys);
+        self
+    }
+
+    /// Replaces the PRIMARY KEY columns, keeping the old ones NOT NULL like
+    /// `replace_order_by`.
+    fn replace_primary_key(mut self, subkeys: Vec<String>) -> Self {
+        let spec = self.spec_mut();
+        spec.primary_key.clear();
+        spec.primary_key.extend_from_slice(subkeys.as_slice());
+        spec.not_null.extend(subkeys);
+        self
+    }
+
+    fn get_table_name(&self) -> String {
+        let spec = self.spec();
+        if let Some(x) = spec.db_name.as_ref() {
+            format!(
+                "{}.{}",
+                Self::identifier(x),
+                Self::identifier(&spec.table_name)
+            )
+        } else {
+            Self::identifier(&spec.table_name)
+        }
+    }
+
+    fn execute_create<E: Executor>(&self, executor: &E) -> InsResult<E::Summary> {
+        executor.execute_query(self.get_create_query()?, None::<std::io::Empty>)
+    }
+
+    fn execute_insert<E: Executor>(
+        &self,
+        executor: &E,
+        frame: &polars::prelude::DataFrame,
+    ) -> InsResult<E::Summary> {
+        let body = self.get_body(frame)?;
+        executor.execute_query(self.get_insert_query()?, Some(body))
+    }
+}
+
 pub fn polars_to_arrow_time_unit(
     tu: &polars::prelude::ArrowTimeUnit,
 ) -> arrow::datatypes::TimeUnit {
@@ -74,6 +348,26 @@ pub fn polars_to_arrow_time_unit(
     }
 }
 
+fn arrow_decimal(precision: usize, scale: usize, wide: bool) -> InsResult<ArDtype> {
+    let max = match wide {
+        true => arrow::datatypes::DECIMAL256_MAX_PRECISION,
+        false => arrow::datatypes::DECIMAL128_MAX_PRECISION,
+    };
+    if precision == 0 || precision > max as usize || scale > precision {
+        return Err(InsError::ConvertError(
+            "arrow decimal",
+            format!(
+                "Decimal({}, {}) needs a precision of 1 to {} and a scale within it",
+                precision, scale, max
+            ),
+        ));
+    }
+    Ok(match wide {
+        true => ArDtype::Decimal256(precision as u8, scale as i8),
+        false => ArDtype::Decimal128(precision as u8, scale as i8),
+    })
+}
+
 pub fn polars_to_arrow_datatype(pl: &PlArrowDtype) -> InsResult<ArDtype> {
     Ok(match pl {
         PlArrowDtype::Null => ArDtype::Null,
@@ -86,8 +380,8 @@ pub fn polars_to_arrow_datatype(pl: &PlArrowDtype) -> InsResult<ArDtype> {
         PlArrowDtype::UInt16 => ArDtype::UInt16,
         PlArrowDtype::UInt32 => ArDtype::UInt32,
         PlArrowDtype::UInt64 => ArDtype::UInt64,
-        PlArrowDtype::Decimal(x, y) => ArDtype::Decimal128(*x as u8, *y as i8),
-        PlArrowDtype::Decimal256(x, y) => ArDtype::Decimal256(*x as u8, *y as i8),
+        PlArrowDtype::Decimal(x, y) => arrow_decimal(*x, *y, false)?,
+        PlArrowDtype::Decimal256(x, y) => arrow_decimal(*x, *y, true)?,
         PlArrowDtype::Float32 => ArDtype::Float32,
         PlArrowDtype::Float64 => ArDtype::Float64,
         PlArrowDtype::Binary => ArDtype::Binary,
@@ -95,6 +389,7 @@ pub fn polars_to_arrow_datatype(pl: &PlArrowDtype) -> InsResult<ArDtype> {
         PlArrowDtype::Utf8View => ArDtype::Binary,
         PlArrowDtype::LargeUtf8 => ArDtype::Binary,
         PlArrowDtype::LargeBinary => ArDtype::Binary,
+        PlArrowDtype::BinaryView => ArDtype::Binary,
         PlArrowDtype::FixedSizeBinary(sz) => ArDtype::FixedSizeBinary(*sz as i32),
         PlArrowDtype::Date32 => ArDtype::Date32,
         PlArrowDtype::Date64 => ArDtype::Date64,
@@ -104,6 +399,7 @@ pub fn polars_to_arrow_datatype(pl: &PlArrowDtype) -> InsResult<ArDtype> {
         ),
         PlArrowDtype::Time32(tu) => ArDtype::Time32(polars_to_arrow_time_unit(tu)),
         PlArrowDtype::Time64(tu) => ArDtype::Time64(polars_to_arrow_time_unit(tu)),
+        PlArrowDtype::Duration(tu) => ArDtype::Duration(polars_to_arrow_time_unit(tu)),
         PlArrowDtype::List(f) => {
             let rf = polars_to_arrow_datatype(f.dtype())?;
             ArDtype::List(Arc::from(ArField::new(f.name.as_str(), rf, f.is_nullable)))
@@ -130,6 +426,19 @@ pub fn polars_to_arrow_datatype(pl: &PlArrowDtype) -> InsResult<ArDtype> {
             }
             ArDtype::Struct(ArFields::from_iter(new_fields))
         }
+        PlArrowDtype::Dictionary(k, v, _) => {
+            let key = match k {
+                IntegerType::Int8 => ArDtype::Int8,
+                IntegerType::Int16 => ArDtype::Int16,
+                IntegerType::Int32 => ArDtype::Int32,
+                IntegerType::Int64 => ArDtype::Int64,
+                IntegerType::UInt8 => ArDtype::UInt8,
+                IntegerType::UInt16 => ArDtype::UInt16,
+                IntegerType::UInt32 => ArDtype::UInt32,
+                IntegerType::UInt64 => ArDtype::UInt64,
+            };
+            ArDtype::Dictionary(Box::new(key), Box::new(polars_to_arrow_datatype(v)?))
+        }
         PlArrowDtype::Map(f, v) => {
             let rf = polars_to_arrow_datatype(f.dtype())?;
             ArDtype::Map(
@@ -171,11 +480,26 @@ fn convert_field(afield: Arc<ArField>) -> Arc<ArField> {
                 afield.is_nullable(),
             ))
         }
+        ArDtype::Duration(_) => Arc::new(ArField::new(
+            afield.name().as_str(),
+            ArDtype::Int64,
+            afield.is_nullable(),
+        )),
         ArDtype::Utf8View => Arc::new(ArField::new(
             afield.name().as_str(),
             ArDtype::Binary,
             afield.is_nullable(),
         )),
+        ArDtype::BinaryView => Arc::new(ArField::new(
+            afield.name().as_str(),
+            ArDtype::Binary,
+            afield.is_nullable(),
+        )),
+        ArDtype::Dictionary(k, v) if **v == ArDtype::Utf8View => Arc::new(ArField::new(
+            afield.name().as_str(),
+            ArDtype::Dictionary(k.clone(), Box::new(ArDtype::Binary)),
+            afield.is_nullable(),
+        )),
         _ => afield,
     }
 }
@@ -195,6 +519,31 @@ fn convert_column(arr: Arc<dyn arrow::array::Array>) -> Arc<dyn arrow::array::Ar
             let gba = builder.finish();
             arrow_array::array::make_array(gba.into_data())
         }
+        // ClickHouse reads no Arrow durations, they travel as their count of units
+        arrow::datatypes::DataType::Duration(_) => {
+            let values = arr
+                .to_data()
+                .into_builder()
+                .data_type(ArDtype::Int64)
+                .build();
+            match values {
+                Ok(x) => arrow_array::array::make_array(x),
+                Err(_) => arr,
+            }
+        }
+        arrow::datatypes::DataType::BinaryView => {
+            let barray = arr.as_binary_view();
+            let mut builder = arrow::array::BinaryBuilder::with_capacity(arr.len(), 8 * 1024);
+            for value in barray.iter() {
+                builder.append_option(value);
+            }
+            let gba = builder.finish();
+            arrow_array::array::make_array(gba.into_data())
+        }
+        arrow::datatypes::DataType::Dictionary(_, _) => {
+            let darray = arr.as_any_dictionary();
+            darray.with_values(convert_column(darray.values().clone()))
+        }
         arrow::datatypes::DataType::LargeList(f) => {
             if no_flatten_required(f) {
                 return arr;
@@ -233,59 +582,612 @@ fn convert_column(arr: Arc<dyn arrow::array::Array>) -> Arc<dyn arrow::array::Ar
     }
 }
 
-pub fn arrow_to_bytes(
-    schema: Arc<arrow::datatypes::Schema>,
-    frame: &polars::prelude::DataFrame,
-) -> InsResult<Vec<u8>> {
-    let mut bytes = vec![];
-    let mut streamer = match StreamWriter::try_new(&mut bytes, &schema) {
-        Ok(x) => x,
-        Err(e) => {
-            return Err(InsError::BuildError("arrow StreamWriter", e.to_string()));
-        }
+fn describe_position(row_offset: Option<usize>, idx: usize) -> String {
+    match row_offset {
+        Some(offset) => format!("row {}", offset + idx),
+        None => format!("nested value {}", idx),
+    }
+}
+
+fn is_numeric(t: &ArDtype) -> bool {
+    t.is_integer()
+        || t.is_floating()
+        || matches!(t, ArDtype::Decimal128(..) | ArDtype::Decimal256(..))
+}
+
+/// Narrows the precision of decimals keeping their scale, which the arrow
+/// cast kernel gets wrong by rounding every value away from zero.
+fn narrow_decimal<T: arrow::datatypes::DecimalType>(
+    arr: &dyn arrow::array::Array,
+    precision: u8,
+    scale: i8,
+    row_offset: Option<usize>,
+) -> InsResult<Arc<dyn arrow::array::Array>> {
+    let values = arr.as_primitive::<T>();
+    let checked = values.null_if_overflow_precision(precision);
+    if let Some(idx) = (0..values.len()).find(|&i| values.is_valid(i) && checked.is_null(i)) {
+        return Err(InsError::ConvertError(
+            "cast to target type",
+            format!(
+                "{} -> {} at {}: {} needs more than {} digits",
+                arr.data_type(),
+                T::TYPE_CONSTRUCTOR(precision, scale),
+                describe_position(row_offset, idx),
+                values.value_as_string(idx),
+                precision
+            ),
+        ));
+    }
+    match checked.with_precision_and_scale(precision, scale) {
+        Ok(x) => Ok(Arc::new(x)),
+        Err(e) => Err(InsError::ConvertError("cast to target type", e.to_string())),
+    }
+}
+
+fn cast_leaf(
+    arr: Arc<dyn arrow::array::Array>,
+    target: &ArDtype,
+    row_offset: Option<usize>,
+) -> InsResult<Arc<dyn arrow::array::Array>> {
+    let options = arrow::compute::CastOptions {
+        safe: false,
+        ..Default::default()
     };
-    let sschema = frame
-        .schema()
-        .iter()
-        .map(|(name, field)| field.to_arrow_field(name.clone(), CompatLevel::newest()))
-        .collect::<Vec<_>>();
-    for chunk in frame.iter_chunks(CompatLevel::newest(), false) {
-        let arrays = chunk.arrays();
-        let mut revarr = arrays.iter().rev().collect::<Vec<_>>();
-        let mut batch = vec![];
-
-        for field in sschema.iter() {
-            let cfield = unsafe {
-                std::mem::transmute::<
-                    polars_arrow::ffi::ArrowSchema,
-                    arrow::array::ffi::FFI_ArrowSchema,
-                >(export_field_to_c(field))
+    let cast = |a: &dyn arrow::array::Array, t: &ArDtype| {
+        arrow::compute::cast_with_options(a, t, &options).map_err(|e| {
+            // a safe cast nulls the values that fail, the first one is reported
+            let failed = arrow::compute::cast(a, t)
+                .ok()
+                .and_then(|x| (0..a.len()).find(|&i| a.is_valid(i) && x.is_null(i)));
+            let position = match failed {
+                Some(idx) => format!(" at {}", describe_position(row_offset, idx)),
+                None => String::new(),
             };
-            let arr = revarr.pop().unwrap();
-            let carr = unsafe {
-                std::mem::transmute::<
-                    polars_arrow::ffi::ArrowArray,
-                    arrow::array::ffi::FFI_ArrowArray,
-                >(export_array_to_c(arr.to_owned()))
+            InsError::ConvertError(
+                "cast to target type",
+                format!("{} -> {}{}: {}", a.data_type(), t, position, e),
+            )
+        })
+    };
+    let is_binary = |t: &ArDtype| {
+        matches!(
+            t,
+            ArDtype::Binary | ArDtype::LargeBinary | ArDtype::BinaryView
+        )
+    };
+    let is_text = |t: &ArDtype| matches!(t, ArDtype::Utf8 | ArDtype::LargeUtf8 | ArDtype::Utf8View);
+    let source = arr.data_type();
+    if is_binary(target) && !is_binary(source) && !is_text(source) {
+        // most types only cast to binary via their string representation
+        let text = cast(arr.as_ref(), &ArDtype::Utf8)?;
+        return cast(text.as_ref(), target);
+    }
+    if is_binary(source) && !is_binary(target) && !is_text(target) {
+        // strings travel as binary, parse them as text
+        let text = cast(arr.as_ref(), &ArDtype::Utf8)?;
+        return cast(text.as_ref(), target);
+    }
+    match (source, target) {
+        (ArDtype::Decimal128(_, s), ArDtype::Decimal128(p, t)) if s == t => {
+            return narrow_decimal::<arrow::datatypes::Decimal128Type>(
+                arr.as_ref(),
+                *p,
+                *t,
+                row_offset,
+            );
+        }
+        (ArDtype::Decimal256(_, s), ArDtype::Decimal256(p, t)) if s == t => {
+            return narrow_decimal::<arrow::datatypes::Decimal256Type>(
+                arr.as_ref(),
+                *p,
+                *t,
+                row_offset,
+            );
+        }
+        _ => {}
+    }
+    let casted = cast(arr.as_ref(), target)?;
+    if is_numeric(source) && is_numeric(target) {
+        // overflow already fails the cast; casting back catches truncation
+        let back = cast(casted.as_ref(), source)?;
+        let changed = match arrow::compute::kernels::cmp::distinct(&arr, &back) {
+            Ok(x) => x,
+            Err(e) => {
+                return Err(InsError::ConvertError("lossy cast check", e.to_string()));
+            }
+        };
+        if changed.true_count() > 0 {
+            let first = changed.values().set_indices().next().unwrap_or_default();
+            return Err(InsError::ConvertError(
+                "lossy cast",
+                format!(
+                    "{} -> {} changes {} value(s), first at {}",
+                    source,
+                    target,
+                    changed.true_count(),
+                    describe_position(row_offset, first)
+                ),
+            ));
+        }
+    }
+    Ok(casted)
+}
+
+/// Casts `arr` to `target`, descending into lists and structs so that only
+/// the leaves that differ are converted. Numeric casts that overflow or
+/// truncate fail instead of silently changing values.
+pub fn coerce_column(
+    arr: Arc<dyn arrow::array::Array>,
+    target: &ArDtype,
+) -> InsResult<Arc<dyn arrow::array::Array>> {
+    coerce_at(arr, target, Some(0))
+}
+
+fn pad_fixed_size(
+    arr: &arrow::array::BinaryArray,
+    size: i32,
+    row_offset: Option<usize>,
+) -> InsResult<Arc<dyn arrow::array::Array>> {
+    let mut builder = arrow::array::FixedSizeBinaryBuilder::with_capacity(arr.len(), size);
+    let mut padded = vec![0u8; size as usize];
+    for (idx, value) in arr.iter().enumerate() {
+        let Some(v) = value else {
+            builder.append_null();
+            continue;
+        };
+        if v.len() > padded.len() {
+            return Err(InsError::ConvertError(
+                "fixed size binary",
+                format!(
+                    "value of {} bytes at {} exceeds {}",
+                    v.len(),
+                    describe_position(row_offset, idx),
+                    size
+                ),
+            ));
+        }
+        padded.fill(0);
+        padded[..v.len()].copy_from_slice(v);
+        if let Err(e) = builder.append_value(&padded) {
+            return Err(InsError::ConvertError("fixed size binary", e.to_string()));
+        }
+    }
+    Ok(Arc::new(builder.finish()))
+}
+
+/// Walks `path` down nested struct fields, carrying the parents' nulls
+/// into the returned child.
+fn struct_field_at(
+    arr: Arc<dyn arrow::array::Array>,
+    path: &[&str],
+) -> InsResult<Arc<dyn arrow::array::Array>> {
+    let mut current = arr;
+    for segment in path {
+        let parent = match current.as_struct_opt() {
+            Some(x) => x,
+            None => {
+                return Err(InsError::ConvertError(
+                    "flattened struct",
+                    format!("{} is not a struct", current.data_type()),
+                ));
+            }
+        };
+        let child = match parent.column_by_name(segment) {
+            Some(x) => x.clone(),
+            None => {
+                return Err(InsError::ConvertError(
+                    "flattened struct",
+                    format!("no field {}", segment),
+                ));
+            }
+        };
+        current = with_parent_nulls(child, parent.nulls())?;
+    }
+    Ok(current)
+}
+
+fn with_parent_nulls(
+    arr: Arc<dyn arrow::array::Array>,
+    parent: Option<&arrow::buffer::NullBuffer>,
+) -> InsResult<Arc<dyn arrow::array::Array>> {
+    if parent.is_none() {
+        return Ok(arr);
+    }
+    let nulls = arrow::buffer::NullBuffer::union(parent, arr.nulls());
+    match arr.to_data().into_builder().nulls(nulls).build() {
+        Ok(x) => Ok(arrow_array::array::make_array(x)),
+        Err(e) => Err(InsError::ConvertError("nested nulls", e.to_string())),
+    }
+}
+
+fn field_values(
+    arr: Arc<dyn arrow::array::Array>,
+    field: &ArField,
+) -> InsResult<Arc<dyn arrow::array::Array>> {
+    if field.is_nullable() {
+        Ok(arr)
+    } else {
+        without_nulls(arr)
+    }
+}
+
+/// Drops the entries of null lists, leaving them empty.
+fn empty_null_entries<O: arrow::array::OffsetSizeTrait>(
+    offsets: &arrow::buffer::OffsetBuffer<O>,
+    values: &dyn arrow::array::Array,
+    nulls: Option<&arrow::buffer::NullBuffer>,
+) -> InsResult<(arrow::buffer::OffsetBuffer<O>, Arc<dyn arrow::array::Array>)> {
+    let mut kept = vec![];
+    let mut new_offsets = vec![O::usize_as(0)];
+    for idx in 0..offsets.len() - 1 {
+        if nulls.is_none_or(|x| x.is_valid(idx)) {
+            kept.extend(offsets[idx].as_usize() as u64..offsets[idx + 1].as_usize() as u64);
+        }
+        new_offsets.push(O::usize_as(kept.len()));
+    }
+    let indices = arrow::array::UInt64Array::from(kept);
+    match arrow::compute::take(values, &indices, None) {
+        Ok(x) => Ok((arrow::buffer::OffsetBuffer::new(new_offsets.into()), x)),
+        Err(e) => Err(InsError::ConvertError("nested nulls", e.to_string())),
+    }
+}
+
+/// Replaces the nulls of a nested array with defaults: null lists and maps
+/// become empty, null structs pass the null down to their nullable members.
+fn without_nulls(arr: Arc<dyn arrow::array::Array>) -> InsResult<Arc<dyn arrow::array::Array>> {
+    if arr.null_count() == 0 {
+        return Ok(arr);
+    }
+    let result = match arr.data_type() {
+        ArDtype::List(_) => {
+            let (field, offsets, values, nulls) = arr.as_list::<i32>().to_owned().into_parts();
+            let (offsets, values) = empty_null_entries(&offsets, values.as_ref(), nulls.as_ref())?;
+            arrow::array::ListArray::try_new(field, offsets, values, None).map(|x| x.into_data())
+        }
+        ArDtype::LargeList(_) => {
+            let (field, offsets, values, nulls) = arr.as_list::<i64>().to_owned().into_parts();
+            let (offsets, values) = empty_null_entries(&offsets, values.as_ref(), nulls.as_ref())?;
+            arrow::array::LargeListArray::try_new(field, offsets, values, None)
+                .map(|x| x.into_data())
+        }
+        ArDtype::Map(_, _) => {
+            let (field, offsets, entries, nulls, sorted) = arr.as_map().to_owned().into_parts();
+            let (offsets, entries) = empty_null_entries(&offsets, &entries, nulls.as_ref())?;
+            let entries = entries.as_struct().to_owned();
+            arrow::array::MapArray::try_new(field, offsets, entries, None, sorted)
+                .map(|x| x.into_data())
+        }
+        ArDtype::FixedSizeList(_, _) => {
+            let (field, size, values, nulls) = arr.as_fixed_size_list().to_owned().into_parts();
+            // each null list covers `size` values
+            let expanded = nulls.map(|n| {
+                let bits = arrow::buffer::BooleanBuffer::collect_bool(values.len(), |i| {
+                    n.is_valid(i / size as usize)
+                });
+                arrow::buffer::NullBuffer::new(bits)
+            });
+            let values = if field.is_nullable() || field.data_type().is_nested() {
+                field_values(with_parent_nulls(values, expanded.as_ref())?, &field)?
+            } else {
+                values
+            };
+            arrow::array::FixedSizeListArray::try_new(field, size, values, None)
+                .map(|x| x.into_data())
+        }
+        ArDtype::Struct(_) => {
+            let (fields, children, nulls) = arr.as_struct().to_owned().into_parts();
+            let mut new_children = vec![];
+            for (child, field) in children.into_iter().zip(fields.iter()) {
+                // non-nullable plain members keep the value stored under the null
+                new_children.push(if field.is_nullable() || field.data_type().is_nested() {
+                    field_values(with_parent_nulls(child, nulls.as_ref())?, field)?
+                } else {
+                    child
+                });
+            }
+            arrow::array::StructArray::try_new(fields, new_children, None).map(|x| x.into_data())
+        }
+        _ => return Ok(arr),
+    };
+    match result {
+        Ok(x) => Ok(arrow_array::array::make_array(x)),
+        Err(e) => Err(InsError::ConvertError("nested nulls", e.to_string())),
+    }
+}
+
+/// Encodes a top-level string column from its text form, or a binary column
+/// already holding the raw bytes, as `semantic`.
+fn encode_semantic(
+    arr: Arc<dyn arrow::array::Array>,
+    semantic: Semantic,
+    row_offset: usize,
+) -> InsResult<Arc<dyn arrow::array::Array>> {
+    let is_text = matches!(
+        arr.data_type(),
+        ArDtype::Utf8 | ArDtype::LargeUtf8 | ArDtype::Utf8View
+    );
+    let binary = cast_leaf(convert_column(arr), &ArDtype::Binary, Some(row_offset))?;
+    let width = match semantic {
+        Semantic::IPv4 => 4,
+        _ => 16,
+    };
+    let mut values = Vec::with_capacity(binary.len());
+    for (idx, value) in binary.as_binary::<i32>().iter().enumerate() {
+        let Some(v) = value else {
+            values.push(None);
+            continue;
+        };
+        let parsed = if is_text {
+            std::str::from_utf8(v).ok().and_then(|x| semantic.parse(x))
+        } else if v.len() == width {
+            // raw IPv4 bytes are in network order like the mapped text form
+            let mut out = [0u8; 16];
+            out[16 - width..].copy_from_slice(v);
+            Some(out)
+        } else {
+            None
+        };
+        match parsed {
+            Some(x) => values.push(Some(x)),
+            None => {
+                return Err(InsError::ConvertError(
+                    "semantic type",
+                    format!(
+                        "unparsable {} {:?} at {}",
+                        semantic.name(),
+                        String::from_utf8_lossy(v),
+                        describe_position(Some(row_offset), idx)
+                    ),
+                ));
+            }
+        }
+    }
+    let encoded: Arc<dyn arrow::array::Array> = match semantic {
+        Semantic::IPv4 => Arc::new(arrow::array::UInt32Array::from_iter(
+            values
+                .into_iter()
+                .map(|x| x.map(|b| u32::from_be_bytes([b[12], b[13], b[14], b[15]]))),
+        )),
+        _ => match arrow::array::FixedSizeBinaryArray::try_from_sparse_iter_with_size(
+            values.into_iter(),
+            16,
+        ) {
+            Ok(x) => Arc::new(x),
+            Err(e) => return Err(InsError::ConvertError("semantic type", e.to_string())),
+        },
+    };
+    Ok(encoded)
+}
+
+fn coerce_at(
+    arr: Arc<dyn arrow::array::Array>,
+    target: &ArDtype,
+    row_offset: Option<usize>,
+) -> InsResult<Arc<dyn arrow::array::Array>> {
+    if arr.data_type() == target {
+        return Ok(arr);
+    }
+    Ok(match (arr.data_type(), target) {
+        (ArDtype::List(_), ArDtype::List(tf)) => {
+            let (_, offsets, a, nulls) = arr.as_list::<i32>().to_owned().into_parts();
+            let values = field_values(coerce_at(a, tf.data_type(), None)?, tf)?;
+            let gba = match arrow::array::ListArray::try_new(tf.clone(), offsets, values, nulls) {
+                Ok(x) => x,
+                Err(e) => return Err(InsError::ConvertError("list values", e.to_string())),
             };
-            let array_data = match unsafe { from_ffi(carr, &cfield) } {
+            arrow_array::array::make_array(gba.into_data())
+        }
+        (ArDtype::LargeList(_), ArDtype::LargeList(tf)) => {
+            let (_, offsets, a, nulls) = arr.as_list::<i64>().to_owned().into_parts();
+            let values = field_values(coerce_at(a, tf.data_type(), None)?, tf)?;
+            let gba =
+                match arrow::array::LargeListArray::try_new(tf.clone(), offsets, values, nulls) {
+                    Ok(x) => x,
+                    Err(e) => return Err(InsError::ConvertError("list values", e.to_string())),
+                };
+            arrow_array::array::make_array(gba.into_data())
+        }
+        (ArDtype::FixedSizeList(_, _), ArDtype::FixedSizeList(tf, sz)) => {
+            let (_, _, a, nulls) = arr.as_fixed_size_list().to_owned().into_parts();
+            let values = field_values(coerce_at(a, tf.data_type(), None)?, tf)?;
+            let gba =
+                match arrow::array::FixedSizeListArray::try_new(tf.clone(), *sz, values, nulls) {
+                    Ok(x) => x,
+                    Err(e) => return Err(InsError::ConvertError("list values", e.to_string())),
+                };
+            arrow_array::array::make_array(gba.into_data())
+        }
+        (ArDtype::Struct(fs), ArDtype::Struct(tfs)) if fs.len() == tfs.len() => {
+            let (_, a, nulls) = arr.as_struct().to_owned().into_parts();
+            let mut new_arrays = vec![];
+            for (child, tf) in a.into_iter().zip(tfs.iter()) {
+                new_arrays.push(field_values(
+                    coerce_at(child, tf.data_type(), row_offset)?,
+                    tf,
+                )?);
+            }
+            let gba = match arrow::array::StructArray::try_new(tfs.clone(), new_arrays, nulls) {
                 Ok(x) => x,
-                Err(e) => {
+                Err(e) => return Err(InsError::ConvertError("struct fields", e.to_string())),
+            };
+            arrow_array::array::make_array(gba.into_data())
+        }
+        (ArDtype::Binary, ArDtype::FixedSizeBinary(size)) => {
+            pad_fixed_size(arr.as_binary::<i32>(), *size, row_offset)?
+        }
+        (ArDtype::LargeList(_), ArDtype::Map(tf, sorted)) => {
+            let (_, offsets, a, nulls) = arr.as_list::<i64>().to_owned().into_parts();
+            let entries = field_values(coerce_at(a, tf.data_type(), None)?, tf)?;
+            // map offsets are 32-bit, unlike the large lists polars exports
+            let offsets = match offsets
+                .iter()
+                .map(|x| i32::try_from(*x))
+                .collect::<Result<Vec<_>, _>>()
+            {
+                Ok(x) => arrow::buffer::OffsetBuffer::new(x.into()),
+                Err(_) => {
                     return Err(InsError::ConvertError(
-                        "bad conversion from polars to arrow",
-                        format!("({:?})\n{}", field, e),
+                        "map entries",
+                        "more than i32::MAX entries in one batch".to_owned(),
                     ));
                 }
             };
-            let arr = arrow_array::array::make_array(array_data);
-            let farr = convert_column(arr);
-            batch.push(farr);
-        }
-        let arrow_batch =
-            arrow_array::RecordBatch::try_new(schema.clone(), batch).expect("batched");
-        match streamer.write(&arrow_batch) {
-            Ok(_) => {}
+            let gba = match arrow::array::MapArray::try_new(
+                tf.clone(),
+                offsets,
+                entries.as_struct().to_owned(),
+                nulls,
+                *sorted,
+            ) {
+                Ok(x) => x,
+                Err(e) => return Err(InsError::ConvertError("map entries", e.to_string())),
+            };
+            arrow_array::array::make_array(gba.into_data())
+        }
+        _ => cast_leaf(arr, target, row_offset)?,
+    })
+}
+
+fn frame_chunk_to_batch(
+    schema: &Arc<arrow::datatypes::Schema>,
+    frame: &polars::prelude::DataFrame,
+    chunk_idx: usize,
+    row_offset: usize,
+) -> InsResult<arrow_array::RecordBatch> {
+    let mut batch = vec![];
+    for (name, column) in frame.get_columns().iter().map(|c| (c.name(), c)) {
+        let field = column
+            .dtype()
+            .to_arrow_field(name.clone(), CompatLevel::newest());
+        let cfield = unsafe {
+            std::mem::transmute::<polars_arrow::ffi::ArrowSchema, arrow::array::ffi::FFI_ArrowSchema>(
+                export_field_to_c(&field),
+            )
+        };
+        let arr = column
+            .as_materialized_series()
+            .to_arrow(chunk_idx, CompatLevel::newest());
+        let carr = unsafe {
+            std::mem::transmute::<polars_arrow::ffi::ArrowArray, arrow::array::ffi::FFI_ArrowArray>(
+                export_array_to_c(arr),
+            )
+        };
+        let array_data = match unsafe { from_ffi(carr, &cfield) } {
+            Ok(x) => x,
             Err(e) => {
+                return Err(InsError::ConvertError(
+                    "bad conversion from polars to arrow",
+                    format!("({:?})\n{}", field, e),
+                ));
+            }
+        };
+        let arr = arrow_array::array::make_array(array_data);
+        let converted = convert_column(arr.clone());
+        // a flattened struct column feeds all the consecutive fields sourced from it
+        let first = batch.len();
+        while let Some(target) = schema.fields().get(batch.len()) {
+            let path = target
+                .metadata()
+                .get(SOURCE_PATH_METADATA)
+                .map(|x| x.split(SOURCE_PATH_SEPARATOR).collect::<Vec<_>>());
+            let source = path.as_ref().map(|x| x[0]).unwrap_or(target.name());
+            if source != name.as_str() {
+                break;
+            }
+            let semantic = target
+                .metadata()
+                .get(SEMANTIC_METADATA)
+                .and_then(|x| Semantic::from_name(x));
+            let coerced = match (path, semantic) {
+                (Some(p), _) => struct_field_at(converted.clone(), &p[1..])
+                    .and_then(|x| coerce_at(x, target.data_type(), Some(row_offset))),
+                (None, Some(x)) => encode_semantic(arr.clone(), x, row_offset),
+                (None, None) => coerce_at(converted.clone(), target.data_type(), Some(row_offset)),
+            };
+            match coerced.and_then(|x| field_values(x, target)) {
+                Ok(x) => batch.push(x),
+                Err(InsError::ConvertError(_, msg)) => {
+                    return Err(InsError::CastError(target.name().to_string(), msg));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if batch.len() == first {
+            return Err(InsError::ConvertError(
+                "column missing from schema",
+                name.to_string(),
+            ));
+        }
+    }
+    match arrow_array::RecordBatch::try_new(schema.clone(), batch) {
+        Ok(x) => Ok(x),
+        Err(e) => Err(InsError::ConvertError(
+            "record batch does not match schema",
+            e.to_string(),
+        )),
+    }
+}
+
+/// Rows per record batch unless set otherwise, ClickHouse's default block size.
+pub const DEFAULT_MAX_BATCH_ROWS: usize = 65_536;
+
+fn check_batch_rows(max_batch_rows: usize) -> InsResult<()> {
+    if max_batch_rows == 0 {
+        return Err(InsError::BuildError(
+            "arrow batches",
+            "max_batch_rows must be positive".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Converts the `max_batch_rows` rows of `frame` starting at `offset`, one
+/// record batch per chunk of the slice. `row_offset` counts the rows before
+/// `frame` for error messages.
+fn slice_to_batches(
+    schema: &Arc<arrow::datatypes::Schema>,
+    frame: &polars::prelude::DataFrame,
+    offset: usize,
+    max_batch_rows: usize,
+    row_offset: usize,
+) -> InsResult<Vec<arrow_array::RecordBatch>> {
+    let mut slice = frame.slice(offset as i64, max_batch_rows);
+    slice.align_chunks();
+    let mut batches = vec![];
+    let mut row_offset = row_offset + offset;
+    for chunk_idx in 0..slice.first_col_n_chunks() {
+        let batch = frame_chunk_to_batch(schema, &slice, chunk_idx, row_offset)?;
+        row_offset += batch.num_rows();
+        batches.push(batch);
+    }
+    Ok(batches)
+}
+
+fn batch_offsets(
+    frame: &polars::prelude::DataFrame,
+    max_batch_rows: usize,
+) -> std::iter::StepBy<std::ops::Range<usize>> {
+    (0..frame.height()).step_by(max_batch_rows)
+}
+
+/// Writes `frame` as an ArrowStream of record batches of at most
+/// `max_batch_rows` rows.
+pub fn arrow_to_writer<W: Write>(
+    schema: Arc<arrow::datatypes::Schema>,
+    frame: &polars::prelude::DataFrame,
+    max_batch_rows: usize,
+    writer: W,
+) -> InsResult<W> {
+    check_batch_rows(max_batch_rows)?;
+    let mut streamer = match StreamWriter::try_new(writer, &schema) {
+        Ok(x) => x,
+        Err(e) => {
+            return Err(InsError::BuildError("arrow StreamWriter", e.to_string()));
+        }
+    };
+    for offset in batch_offsets(frame, max_batch_rows) {
+        for arrow_batch in slice_to_batches(&schema, frame, offset, max_batch_rows, 0)? {
+            if let Err(e) = streamer.write(&arrow_batch) {
                 return Err(InsError::ConvertError(
                     "failed writing batch to stream",
                     e.to_string(),
@@ -293,5 +1195,200 @@ pub fn arrow_to_bytes(
             }
         }
     }
-    Ok(bytes)
+    match streamer.into_inner() {
+        Ok(x) => Ok(x),
+        Err(e) => Err(InsError::ConvertError(
+            "failed finishing stream",
+            e.to_string(),
+        )),
+    }
+}
+
+pub fn arrow_to_bytes(
+    schema: Arc<arrow::datatypes::Schema>,
+    frame: &polars::prelude::DataFrame,
+    max_batch_rows: usize,
+) -> InsResult<Vec<u8>> {
+    arrow_to_writer(schema, frame, max_batch_rows, vec![])
+}
+
+/// Encodes frames into an ArrowStream one record batch of at most
+/// `max_batch_rows` rows at a time. Each item holds the bytes of a single
+/// batch (the first also carries the schema message, the last the
+/// end-of-stream marker), and only the batch being handed out is held
+/// encoded. Every slice of a frame is converted once before its first batch
+/// is encoded, so a frame that fails to cast never reaches the stream
+/// partially; the first frame is checked when the stream is created.
+pub struct ArrowStreamChunks {
+    schema: Arc<arrow::datatypes::Schema>,
+    frames: Box<dyn Iterator<Item = InsResult<polars::prelude::DataFrame>> + Send>,
+    max_batch_rows: usize,
+    frame: Option<polars::prelude::DataFrame>,
+    frame_pos: usize,
+    batches: std::collections::VecDeque<arrow_array::RecordBatch>,
+    streamer: Option<StreamWriter<Vec<u8>>>,
+    pending: Vec<u8>,
+    pending_pos: usize,
+    row_offset: usize,
+    failure: Arc<std::sync::Mutex<Option<InsError>>>,
+}
+
+impl ArrowStreamChunks {
+    pub fn new(
+        schema: Arc<arrow::datatypes::Schema>,
+        frame: polars::prelude::DataFrame,
+        max_batch_rows: usize,
+    ) -> InsResult<Self> {
+        Self::from_frames(schema, std::iter::once(Ok(frame)), max_batch_rows)
+    }
+
+    pub fn from_frames(
+        schema: Arc<arrow::datatypes::Schema>,
+        frames: impl Iterator<Item = InsResult<polars::prelude::DataFrame>> + Send + 'static,
+        max_batch_rows: usize,
+    ) -> InsResult<Self> {
+        check_batch_rows(max_batch_rows)?;
+        let streamer = match StreamWriter::try_new(vec![], &schema) {
+            Ok(x) => x,
+            Err(e) => {
+                return Err(InsError::BuildError("arrow StreamWriter", e.to_string()));
+            }
+        };
+        let mut chunks = Self {
+            schema,
+            frames: Box::new(frames),
+            max_batch_rows,
+            frame: None,
+            frame_pos: 0,
+            batches: std::collections::VecDeque::new(),
+            streamer: Some(streamer),
+            pending: vec![],
+            pending_pos: 0,
+            row_offset: 0,
+            failure: Arc::new(std::sync::Mutex::new(None)),
+        };
+        if let Some(Err(e)) = chunks.load_next_frame() {
+            return Err(e);
+        }
+        Ok(chunks)
+    }
+
+    /// Holds the error that ended a stream consumed through `Read`, which
+    /// only passes on its message.
+    pub fn failure(&self) -> Arc<std::sync::Mutex<Option<InsError>>> {
+        self.failure.clone()
+    }
+
+    /// Takes the next frame once all its slices convert.
+    fn load_next_frame(&mut self) -> Option<InsResult<()>> {
+        if let Some(frame) = self.frame.take() {
+            self.row_offset += frame.height();
+        }
+        let result = self.frames.next()?.and_then(|frame| {
+            for offset in batch_offsets(&frame, self.max_batch_rows) {
+                slice_to_batches(
+                    &self.schema,
+                    &frame,
+                    offset,
+                    self.max_batch_rows,
+                    self.row_offset,
+                )?;
+            }
+            self.frame = Some(frame);
+            self.frame_pos = 0;
+            Ok(())
+        });
+        if result.is_err() {
+            self.streamer = None;
+        }
+        Some(result)
+    }
+
+    fn next_batch(&mut self) -> Option<InsResult<arrow_array::RecordBatch>> {
+        loop {
+            if let Some(x) = self.batches.pop_front() {
+                return Some(Ok(x));
+            }
+            if let Some(frame) = self.frame.as_ref()
+                && self.frame_pos < frame.height()
+            {
+                let batches = slice_to_batches(
+                    &self.schema,
+                    frame,
+                    self.frame_pos,
+                    self.max_batch_rows,
+                    self.row_offset,
+                );
+                self.frame_pos += self.max_batch_rows;
+                match batches {
+                    Ok(x) => self.batches.extend(x),
+                    Err(e) => return Some(Err(e)),
+                }
+                continue;
+            }
+            match self.load_next_frame()? {
+                Ok(()) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl Iterator for ArrowStreamChunks {
+    type Item = InsResult<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.streamer.as_ref()?;
+        let result = match self.next_batch() {
+            Some(Ok(batch)) => {
+                let streamer = self.streamer.as_mut()?;
+                match streamer.write(&batch) {
+                    Ok(()) => Ok(std::mem::take(streamer.get_mut())),
+                    Err(e) => Err(InsError::ConvertError(
+                        "failed writing batch to stream",
+                        e.to_string(),
+                    )),
+                }
+            }
+            Some(Err(e)) => Err(e),
+            None => {
+                return match self.streamer.take()?.into_inner() {
+                    Ok(x) => Some(Ok(x)),
+                    Err(e) => Some(Err(InsError::ConvertError(
+                        "failed finishing stream",
+                        e.to_string(),
+                    ))),
+                };
+            }
+        };
+        if result.is_err() {
+            self.streamer = None;
+        }
+        Some(result)
+    }
+}
+
+impl Read for ArrowStreamChunks {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pending_pos >= self.pending.len() {
+            match self.next() {
+                Some(Ok(x)) => {
+                    self.pending = x;
+                    self.pending_pos = 0;
+                }
+                Some(Err(e)) => {
+                    let message = e.to_string();
+                    if let Ok(mut x) = self.failure.lock() {
+                        *x = Some(e);
+                    }
+                    return Err(std::io::Error::other(message));
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.pending.len() - self.pending_pos);
+        buf[..n].copy_from_slice(&self.pending[self.pending_pos..self.pending_pos + n]);
+        self.pending_pos += n;
+        Ok(n)
+    }
 }
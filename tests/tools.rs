@@ -1,11 +1,19 @@
 #[cfg(test)]
 pub mod tests {
-    use inserter_x::{clickhouse::ClickhouseInserter, common::PlDtype};
+    use arrow_array::RecordBatch;
+    use arrow_ipc::reader::StreamReader;
+    use inserter_x::{
+        clickhouse::ClickhouseInserter,
+        common::{Inserter, PlDtype},
+    };
     use polars::{
         df,
         frame::DataFrame,
         io::SerReader,
-        prelude::{IntoLazy, JsonReader, LazyFrame, col},
+        prelude::{
+            Int64Chunked, IntoColumn, IntoLazy, IntoSeries, JsonReader, LazyFrame, NamedFrom,
+            TimeUnit, col,
+        },
         sql::SQLContext,
     };
 
@@ -46,6 +54,35 @@ pub mod tests {
         .unwrap()
     }
 
+    pub fn get_sample_df_datetime() -> DataFrame {
+        let ts = [1_700_000_000_123_456_789i64, -86_400_000_000_000, 0];
+        let units = [
+            ("ms", TimeUnit::Milliseconds, 1_000_000),
+            ("us", TimeUnit::Microseconds, 1_000),
+            ("ns", TimeUnit::Nanoseconds, 1),
+        ];
+        let mut columns = vec![];
+        for (suffix, tu, div) in units {
+            for tz in [None, Some("Asia/Singapore")] {
+                let name = format!("{}_{}", suffix, if tz.is_some() { "tz" } else { "naive" });
+                let values = ts.iter().map(|x| x / div).collect::<Vec<_>>();
+                let column = Int64Chunked::new(name.into(), &values)
+                    .into_datetime(tu, tz.map(|x| x.into()))
+                    .into_series()
+                    .into_column();
+                columns.push(column);
+            }
+        }
+        DataFrame::new(columns).unwrap()
+    }
+
+    pub fn read_arrow_body(body: Vec<u8>) -> Vec<RecordBatch> {
+        StreamReader::try_new(std::io::Cursor::new(body), None)
+            .expect("arrow stream")
+            .map(|x| x.expect("record batch"))
+            .collect()
+    }
+
     pub fn run_sql(query: &str, frames: &[(&str, LazyFrame)]) -> LazyFrame {
         let mut context = SQLContext::new();
         for (name, frame) in frames {
@@ -128,6 +165,129 @@ pub mod tests {
             .unwrap()
     }
 
+    #[cfg(feature = "client")]
+    pub struct RecordedRequest {
+        pub target: String,
+        pub headers: Vec<(String, String)>,
+        pub body: Vec<u8>,
+    }
+
+    #[cfg(feature = "client")]
+    impl RecordedRequest {
+        pub fn query(&self) -> String {
+            let raw = self
+                .target
+                .split_once("query=")
+                .map(|(_, q)| q.split('&').next().unwrap_or_default())
+                .unwrap_or_default()
+                .replace('+', " ");
+            let bytes = raw.as_bytes();
+            let mut decoded = vec![];
+            let mut idx = 0;
+            while idx < bytes.len() {
+                if bytes[idx] == b'%' && idx + 2 < bytes.len() {
+                    let hex = std::str::from_utf8(&bytes[idx + 1..idx + 3]).unwrap();
+                    decoded.push(u8::from_str_radix(hex, 16).unwrap());
+                    idx += 3;
+                } else {
+                    decoded.push(bytes[idx]);
+                    idx += 1;
+                }
+            }
+            String::from_utf8(decoded).unwrap()
+        }
+
+        pub fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.as_str())
+        }
+    }
+
+    #[cfg(feature = "client")]
+    pub type CannedResponse = (u16, Vec<(&'static str, String)>, String);
+
+    /// Serves one canned `(status, headers, body)` response per accepted connection
+    /// and hands back everything it received once all responses are sent.
+    #[cfg(feature = "client")]
+    pub fn stand_in_server(
+        responses: Vec<CannedResponse>,
+    ) -> (String, std::thread::JoinHandle<Vec<RecordedRequest>>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut recorded = vec![];
+            for (status, headers, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let target = line.split(' ').nth(1).unwrap_or_default().to_owned();
+                let mut req_headers = vec![];
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    let trimmed = line.trim_end();
+                    if trimmed.is_empty() {
+                        break;
+                    }
+                    if let Some((k, v)) = trimmed.split_once(':') {
+                        req_headers.push((k.trim().to_owned(), v.trim().to_owned()));
+                    }
+                }
+                let header = |name: &str| {
+                    req_headers
+                        .iter()
+                        .find(|(k, _): &&(String, String)| k.eq_ignore_ascii_case(name))
+                        .map(|(_, v)| v.clone())
+                };
+                let mut req_body = vec![];
+                if let Some(len) = header("Content-Length") {
+                    req_body.resize(len.parse().unwrap(), 0);
+                    reader.read_exact(&mut req_body).unwrap();
+                } else if header("Transfer-Encoding").is_some_and(|x| x.contains("chunked")) {
+                    // an aborted body ends the connection without the final chunk
+                    loop {
+                        line.clear();
+                        let _ = reader.read_line(&mut line);
+                        let Ok(size) = usize::from_str_radix(line.trim(), 16) else {
+                            break;
+                        };
+                        let mut chunk = vec![0; size + 2];
+                        if size == 0 || reader.read_exact(&mut chunk).is_err() {
+                            break;
+                        }
+                        req_body.extend_from_slice(&chunk[..size]);
+                    }
+                }
+                let mut response = format!(
+                    "HTTP/1.1 {} Stand-in\r\nContent-Length: {}\r\nConnection: close\r\n",
+                    status,
+                    body.len()
+                );
+                for (k, v) in headers {
+                    response.push_str(&format!("{}: {}\r\n", k, v));
+                }
+                response.push_str("\r\n");
+                response.push_str(&body);
+                let mut stream = stream;
+                let _ = stream
+                    .write_all(response.as_bytes())
+                    .and_then(|_| stream.flush());
+                recorded.push(RecordedRequest {
+                    target,
+                    headers: req_headers,
+                    body: req_body,
+                });
+            }
+            recorded
+        });
+        (url, handle)
+    }
+
     pub fn parse_json_from_url(table_name: &str, url: &str) -> (String, DataFrame) {
         let response = reqwest::blocking::get(url).unwrap();
         if let Err(e) = response.error_for_status_ref() {